@@ -6,6 +6,7 @@ use std::str;
 pub struct Forest<'a>(&'a [Tree<'a>]);
 
 impl<'a> Forest<'a> {
+    #[allow(clippy::self_named_constructors)]
     fn forest(forest: &'a [Tree<'a>]) -> Forest<'a> {
         Forest(forest)
     }
@@ -0,0 +1,98 @@
+//! Replacing deeply nested documents with a placeholder (see `DocBuilder::elide_depth`).
+
+use std::borrow::Cow;
+
+use crate::{Doc, DocAllocator};
+
+pub(crate) struct Elide<'a, D>
+where
+    D: ?Sized,
+{
+    allocator: &'a D,
+    max_depth: usize,
+    placeholder: Cow<'a, str>,
+}
+
+impl<D> Clone for Elide<'_, D>
+where
+    D: ?Sized,
+{
+    fn clone(&self) -> Self {
+        Elide {
+            allocator: self.allocator,
+            max_depth: self.max_depth,
+            placeholder: self.placeholder.clone(),
+        }
+    }
+}
+
+impl<'a, D> Elide<'a, D>
+where
+    D: ?Sized,
+{
+    pub(crate) fn new(allocator: &'a D, max_depth: usize, placeholder: Cow<'a, str>) -> Self {
+        Elide {
+            allocator,
+            max_depth,
+            placeholder,
+        }
+    }
+
+    /// Copies `doc`, which is nested inside `depth` groups and annotations, replacing the groups
+    /// and annotations which are nested too deep
+    pub(crate) fn doc<A>(&self, doc: &Doc<'a, D::Doc, A>, depth: usize) -> D::Doc
+    where
+        D: DocAllocator<'a, A>,
+        A: Clone + 'a,
+    {
+        let allocator = self.allocator;
+        let doc = match *doc {
+            Doc::Group(_) | Doc::Annotated(..) if depth >= self.max_depth => {
+                return allocator.text(self.placeholder.clone()).into_doc()
+            }
+            Doc::Nil => Doc::Nil,
+            Doc::Append(..) => {
+                // Long lists are appended together so walk them with an explicit stack instead of
+                // recursing on every element
+                let mut docs = Vec::new();
+                let mut stack = vec![doc];
+                while let Some(doc) = stack.pop() {
+                    match *doc {
+                        Doc::Append(ref l, ref r) => {
+                            stack.push(r);
+                            stack.push(l);
+                        }
+                        _ => docs.push(self.doc(doc, depth)),
+                    }
+                }
+                let append = |l, r| allocator.alloc(Doc::Append(l, r));
+                return docs.into_iter().reduce(append).unwrap();
+            }
+            Doc::Group(ref doc) => Doc::Group(self.doc(doc, depth + 1)),
+            Doc::FlatAlt(ref l, ref r) => Doc::FlatAlt(self.doc(l, depth), self.doc(r, depth)),
+            Doc::Nest(offset, ref doc) => Doc::Nest(offset, self.doc(doc, depth)),
+            Doc::Align(ref doc) => Doc::Align(self.doc(doc, depth)),
+            Doc::Hardline => Doc::Hardline,
+            Doc::RenderLen(len, ref doc) => Doc::RenderLen(len, self.doc(doc, depth)),
+            Doc::MeasuredText(len, ref doc) => Doc::MeasuredText(len, self.doc(doc, depth)),
+            Doc::OwnedText(ref s) => Doc::OwnedText(s.clone()),
+            Doc::BorrowedText(s) => Doc::BorrowedText(s),
+            Doc::SmallText(s) => Doc::SmallText(s),
+            Doc::Annotated(ref ann, ref doc) => {
+                Doc::Annotated(ann.clone(), self.doc(doc, depth + 1))
+            }
+            Doc::Union(ref l, ref r) => Doc::Union(self.doc(l, depth), self.doc(r, depth)),
+            Doc::Column(ref f) => {
+                let (this, f) = (self.clone(), f.clone());
+                Doc::Column(allocator.alloc_column_fn(move |column| this.doc(&f(column), depth)))
+            }
+            Doc::Nesting(ref f) => {
+                let (this, f) = (self.clone(), f.clone());
+                Doc::Nesting(allocator.alloc_column_fn(move |nesting| this.doc(&f(nesting), depth)))
+            }
+            Doc::Fail => Doc::Fail,
+            Doc::FailWith(ref message) => Doc::FailWith(message.clone()),
+        };
+        allocator.alloc(doc)
+    }
+}
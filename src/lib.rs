@@ -150,33 +150,54 @@ use std::{
 
 #[cfg(feature = "termcolor")]
 use termcolor::{ColorSpec, WriteColor};
+use unicode_segmentation::UnicodeSegmentation;
 
 pub mod block;
+mod elide;
+pub mod list;
 mod render;
+pub mod table;
 
 pub use self::block::{Affixes, BlockDoc};
+pub use self::list::{EncloseSep, ListStyle};
 #[cfg(feature = "termcolor")]
 pub use self::render::TermColored;
-pub use self::render::{FmtWrite, IoWrite, Render, RenderAnnotated};
+pub use self::render::{
+    CostFunction, Cursor, DefaultCost, FmtWrite, Indentation, IoWrite, Layout, LayoutStats,
+    LayoutToken, LayoutTracer, Overflow, OverflowWriter, Position, Render, RenderAnnotated,
+    RenderError, RenderOptions, Rewind, Span, SpanWriter, StreamRender, Tokens, UnicodeWidth,
+    WidthMeasure,
+};
+pub use self::table::{Alignment, Table};
 
 /// The concrete document type. This type is not meant to be used directly. Instead use the static
 /// functions on `Doc` or the methods on an `DocAllocator`.
 ///
 /// The `T` parameter is used to abstract over pointers to `Doc`. See `RefDoc` and `BoxDoc` for how
 /// it is used
-#[derive(Clone)]
+///
+/// New kinds of documents may be added without a breaking change, so matching on a `Doc` needs a
+/// wildcard arm.
+#[derive(Clone, Default)]
+#[non_exhaustive]
 pub enum Doc<'a, T, A = ()>
 where
     T: DocPtr<'a, A>,
 {
+    #[default]
     Nil,
     Append(T, T),
     Group(T),
     FlatAlt(T, T),
     Nest(isize, T),
+    // Sets the indentation to the current column
+    Align(T),
     Hardline,
-    // Stores the length of a string document that is not just ascii
+    // Stores the length of a string document, which is used regardless of the width measure
     RenderLen(usize, T),
+    // Stores the width of a string document that is not just ascii, as measured by the default
+    // `UnicodeWidth`. Renderers using a custom `RenderOptions::width_measure` measure it again
+    MeasuredText(usize, T),
     OwnedText(Box<str>),
     BorrowedText(&'a str),
     SmallText(SmallText),
@@ -185,15 +206,8 @@ where
     Column(T::ColumnFn),
     Nesting(T::ColumnFn),
     Fail,
-}
-
-impl<'a, T, A> Default for Doc<'a, T, A>
-where
-    T: DocPtr<'a, A>,
-{
-    fn default() -> Self {
-        Self::Nil
-    }
+    // Makes rendering fail like `Fail`, reporting the message
+    FailWith(Box<str>),
 }
 
 pub type SmallText = arrayvec::ArrayString<[u8; 22]>;
@@ -255,8 +269,9 @@ where
                 f.debug_tuple("Group").field(doc).finish()
             }
             Doc::Nest(off, ref doc) => f.debug_tuple("Nest").field(&off).field(doc).finish(),
+            Doc::Align(ref doc) => f.debug_tuple("Align").field(doc).finish(),
             Doc::Hardline => f.debug_tuple("Hardline").finish(),
-            Doc::RenderLen(_, d) => d.fmt(f),
+            Doc::RenderLen(_, d) | Doc::MeasuredText(_, d) => d.fmt(f),
             Doc::OwnedText(ref s) => s.fmt(f),
             Doc::BorrowedText(ref s) => s.fmt(f),
             Doc::SmallText(ref s) => s.fmt(f),
@@ -267,6 +282,7 @@ where
             Doc::Column(_) => f.debug_tuple("Column(..)").finish(),
             Doc::Nesting(_) => f.debug_tuple("Nesting(..)").finish(),
             Doc::Fail => f.debug_tuple("Fail").finish(),
+            Doc::FailWith(ref message) => f.debug_tuple("FailWith").field(message).finish(),
         }
     }
 }
@@ -356,6 +372,12 @@ macro_rules! impl_doc {
                 $allocator.text(data).into_doc()
             }
 
+            /// The given text, with its line breaks turned into `hardline`s.
+            #[inline]
+            pub fn multiline_text<U: Into<Cow<'a, str>>>(data: U) -> Self {
+                $allocator.multiline_text(data).into_doc()
+            }
+
             /// Append the given document after this document.
             #[inline]
             pub fn append<D>(self, that: D) -> Self
@@ -393,6 +415,122 @@ macro_rules! impl_doc {
                 $allocator.intersperse(docs, separator).into_doc()
             }
 
+            /// A single document concatenating the given documents separated by `space`.
+            #[inline]
+            pub fn hsep<I>(docs: I) -> Self
+            where
+                I: IntoIterator,
+                I::Item: Pretty<'a, $allocator, A>,
+            {
+                $allocator.hsep(docs).into_doc()
+            }
+
+            /// A single document concatenating the given documents separated by `line`.
+            #[inline]
+            pub fn vsep<I>(docs: I) -> Self
+            where
+                I: IntoIterator,
+                I::Item: Pretty<'a, $allocator, A>,
+                A: Clone,
+            {
+                $allocator.vsep(docs).into_doc()
+            }
+
+            /// `vsep` grouped, so that the documents are separated by spaces if they fit on a
+            /// single line.
+            #[inline]
+            pub fn sep<I>(docs: I) -> Self
+            where
+                I: IntoIterator,
+                I::Item: Pretty<'a, $allocator, A>,
+                A: Clone,
+            {
+                $allocator.sep(docs).into_doc()
+            }
+
+            /// A single document concatenating the given documents (the same as `concat`).
+            #[inline]
+            pub fn hcat<I>(docs: I) -> Self
+            where
+                I: IntoIterator,
+                I::Item: Pretty<'a, $allocator, A>,
+            {
+                $allocator.hcat(docs).into_doc()
+            }
+
+            /// A single document concatenating the given documents separated by `line_`.
+            #[inline]
+            pub fn vcat<I>(docs: I) -> Self
+            where
+                I: IntoIterator,
+                I::Item: Pretty<'a, $allocator, A>,
+                A: Clone,
+            {
+                $allocator.vcat(docs).into_doc()
+            }
+
+            /// `vcat` grouped, so that the documents are not separated if they fit on a single
+            /// line.
+            #[inline]
+            pub fn cat<I>(docs: I) -> Self
+            where
+                I: IntoIterator,
+                I::Item: Pretty<'a, $allocator, A>,
+                A: Clone,
+            {
+                $allocator.cat(docs).into_doc()
+            }
+
+            /// Appends `punctuation` to every document except the last.
+            #[inline]
+            pub fn punctuate<I, P>(docs: I, punctuation: P) -> Vec<Self>
+            where
+                I: IntoIterator,
+                I::Item: Pretty<'a, $allocator, A>,
+                P: Pretty<'a, $allocator, A> + Clone,
+            {
+                $allocator
+                    .punctuate(docs, punctuation)
+                    .into_iter()
+                    .map(DocBuilder::into_doc)
+                    .collect()
+            }
+
+            /// A single document concatenating the given documents, putting as many of them on
+            /// each line as fit. Each `separator` is only broken if the document after it does
+            /// not fit on the rest of the line.
+            #[inline]
+            pub fn fill<I, S>(docs: I, separator: S) -> Self
+            where
+                I: IntoIterator,
+                I::Item: Pretty<'a, $allocator, A>,
+                S: Pretty<'a, $allocator, A> + Clone,
+            {
+                $allocator.fill(docs, separator).into_doc()
+            }
+
+            /// `fill` with `line` as the separator.
+            #[inline]
+            pub fn fill_sep<I>(docs: I) -> Self
+            where
+                I: IntoIterator,
+                I::Item: Pretty<'a, $allocator, A>,
+                A: Clone,
+            {
+                $allocator.fill_sep(docs).into_doc()
+            }
+
+            /// `fill` with `line_` as the separator.
+            #[inline]
+            pub fn fill_cat<I>(docs: I) -> Self
+            where
+                I: IntoIterator,
+                I::Item: Pretty<'a, $allocator, A>,
+                A: Clone,
+            {
+                $allocator.fill_cat(docs).into_doc()
+            }
+
             /// Acts as `self` when laid out on multiple lines and acts as `that` when laid out on a single line.
             #[inline]
             pub fn flat_alt<D>(self, doc: D) -> Self
@@ -436,6 +574,50 @@ macro_rules! impl_doc {
                 DocBuilder(&$allocator, self.into()).union(other).into_doc()
             }
 
+            /// Replaces the groups and annotations which are nested more than `max_depth` groups
+            /// and annotations deep with `placeholder`.
+            #[inline]
+            pub fn elide_depth(self, max_depth: usize, placeholder: impl Into<Cow<'a, str>>) -> Self
+            where
+                A: Clone,
+            {
+                DocBuilder(&$allocator, self.into())
+                    .elide_depth(max_depth, placeholder)
+                    .into_doc()
+            }
+
+            /// Shortens a text document which is wider than `max_width` columns, ending it with
+            /// `ellipsis`.
+            #[inline]
+            pub fn truncate(self, max_width: usize, ellipsis: impl Into<Cow<'a, str>>) -> Self {
+                DocBuilder(&$allocator, self.into())
+                    .truncate(max_width, ellipsis)
+                    .into_doc()
+            }
+
+            /// Pads this document with `pad` on the right until it is `width` columns wide.
+            #[inline]
+            pub fn fill_to(self, width: usize, pad: char) -> Self
+            where
+                A: Clone,
+            {
+                DocBuilder(&$allocator, self.into())
+                    .fill_to(width, pad)
+                    .into_doc()
+            }
+
+            /// Pads this document like `fill_to`, or breaks the line after it if it is wider
+            /// than `width` columns.
+            #[inline]
+            pub fn fill_break(self, width: usize, pad: char) -> Self
+            where
+                A: Clone,
+            {
+                DocBuilder(&$allocator, self.into())
+                    .fill_break(width, pad)
+                    .into_doc()
+            }
+
             #[inline]
             pub fn softline() -> Self {
                 Self::line().group()
@@ -508,6 +690,14 @@ macro_rules! impl_doc_methods {
             pub fn fail() -> Self {
                 Doc::Fail.into()
             }
+
+            /// Fails document rendering immediately, reporting `message` (see
+            /// `DocAllocator::fail_with`).
+            #[inline]
+            pub fn fail_with<U: Into<Cow<'a, str>>>(message: U) -> Self {
+                let message: Cow<'a, str> = message.into();
+                Doc::FailWith(message.into()).into()
+            }
         }
 
         impl< $($params)* > $name< $($params)* >
@@ -556,6 +746,12 @@ where
         T::ALLOCATOR.text(data).1
     }
 
+    /// The given text, with its line breaks turned into `hardline`s.
+    #[inline]
+    pub fn multiline_text<U: Into<Cow<'a, str>>>(data: U) -> Self {
+        T::ALLOCATOR.multiline_text(data).1
+    }
+
     fn flat_alt<D>(self, doc: D) -> Self
     where
         D: Pretty<'a, T::Allocator, A>,
@@ -582,6 +778,12 @@ where
         T::ALLOCATOR.text(data).into_plain_doc()
     }
 
+    /// The given text, with its line breaks turned into `hardline`s.
+    #[inline]
+    pub fn multiline_text<U: Into<Cow<'a, str>>>(data: U) -> Self {
+        T::ALLOCATOR.multiline_text(data).into_plain_doc()
+    }
+
     fn flat_alt<D>(self, doc: D) -> Self
     where
         D: Pretty<'a, T::Allocator, A>,
@@ -616,7 +818,7 @@ where
     T: DocPtr<'a, A> + 'a,
 {
     doc: &'d Doc<'a, T, A>,
-    width: usize,
+    options: RenderOptions,
 }
 
 impl<'a, T, A> fmt::Display for PrettyFmt<'a, '_, T, A>
@@ -624,7 +826,7 @@ where
     T: DocPtr<'a, A>,
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        self.doc.render_fmt(self.width, f)
+        self.doc.render_fmt_with(&self.options, f)
     }
 }
 
@@ -638,7 +840,16 @@ where
     where
         W: ?Sized + io::Write,
     {
-        self.render_raw(width, &mut IoWrite::new(out))
+        self.render_with(&RenderOptions::new(width), out)
+    }
+
+    /// Writes a rendered document to a `std::io::Write` object using the given `options`.
+    #[inline]
+    pub fn render_with<W>(&self, options: &RenderOptions, out: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + io::Write,
+    {
+        self.render_raw_with(options, &mut IoWrite::new(out))
     }
 
     /// Writes a rendered document to a `std::fmt::Write` object.
@@ -647,7 +858,16 @@ where
     where
         W: ?Sized + fmt::Write,
     {
-        self.render_raw(width, &mut FmtWrite::new(out))
+        self.render_fmt_with(&RenderOptions::new(width), out)
+    }
+
+    /// Writes a rendered document to a `std::fmt::Write` object using the given `options`.
+    #[inline]
+    pub fn render_fmt_with<W>(&self, options: &RenderOptions, out: &mut W) -> fmt::Result
+    where
+        W: ?Sized + fmt::Write,
+    {
+        self.render_raw_with(options, &mut FmtWrite::new(out))
     }
 
     /// Writes a rendered document to a `RenderAnnotated<A>` object.
@@ -657,7 +877,137 @@ where
         for<'b> W: render::RenderAnnotated<'b, A>,
         W: ?Sized,
     {
-        render::best(self, width, out)
+        self.render_raw_with(&RenderOptions::new(width), out)
+    }
+
+    /// Writes a rendered document to a `RenderAnnotated<A>` object using the given `options`.
+    #[inline]
+    pub fn render_raw_with<W>(&self, options: &RenderOptions, out: &mut W) -> Result<(), W::Error>
+    where
+        for<'b> W: render::RenderAnnotated<'b, A>,
+        W: ?Sized,
+    {
+        render::best(self, options, out)
+    }
+
+    /// Writes a rendered document to a `RenderAnnotated<A>` object using the given `options`,
+    /// reporting the work done to lay it out to `tracer` (see `LayoutStats`).
+    ///
+    /// Only the greedy layout algorithm reports to the tracer.
+    #[inline]
+    pub fn render_raw_traced<W, Tr>(
+        &self,
+        options: &RenderOptions,
+        out: &mut W,
+        tracer: &mut Tr,
+    ) -> Result<(), W::Error>
+    where
+        for<'b> W: render::RenderAnnotated<'b, A>,
+        W: ?Sized,
+        Tr: ?Sized + LayoutTracer,
+    {
+        render::best_traced(self, options, out, tracer)
+    }
+
+    /// Writes a rendered document to a `std::fmt::Write` object and returns the span of each
+    /// annotated document in the output (see `SpanWriter`).
+    #[inline]
+    pub fn render_fmt_spans<W>(&self, width: usize, out: &mut W) -> Result<Vec<Span<A>>, fmt::Error>
+    where
+        W: ?Sized + fmt::Write,
+        A: Clone,
+    {
+        self.render_fmt_spans_with(&RenderOptions::new(width), out)
+    }
+
+    /// Writes a rendered document to a `std::fmt::Write` object using the given `options` and
+    /// returns the span of each annotated document in the output.
+    #[inline]
+    pub fn render_fmt_spans_with<W>(
+        &self,
+        options: &RenderOptions,
+        out: &mut W,
+    ) -> Result<Vec<Span<A>>, fmt::Error>
+    where
+        W: ?Sized + fmt::Write,
+        A: Clone,
+    {
+        let mut writer = SpanWriter::new(FmtWrite::new(out));
+        self.render_raw_with(options, &mut writer)?;
+        Ok(writer.into_spans())
+    }
+
+    /// Writes a rendered document to a `std::fmt::Write` object and returns every line which
+    /// is wider than `width` (see `OverflowWriter`).
+    #[inline]
+    pub fn render_fmt_overflows<W>(
+        &self,
+        width: usize,
+        out: &mut W,
+    ) -> Result<Vec<Overflow<A>>, fmt::Error>
+    where
+        W: ?Sized + fmt::Write,
+        A: Clone,
+    {
+        self.render_fmt_overflows_with(&RenderOptions::new(width), out)
+    }
+
+    /// Writes a rendered document to a `std::fmt::Write` object using the given `options` and
+    /// returns every line which is wider than the width of `options`.
+    #[inline]
+    pub fn render_fmt_overflows_with<W>(
+        &self,
+        options: &RenderOptions,
+        out: &mut W,
+    ) -> Result<Vec<Overflow<A>>, fmt::Error>
+    where
+        W: ?Sized + fmt::Write,
+        A: Clone,
+    {
+        let mut writer = OverflowWriter::new(FmtWrite::new(out), options);
+        self.render_raw_with(options, &mut writer)?;
+        Ok(writer.into_overflows())
+    }
+
+    /// Renders the document to a `String`. If it fails to render, the returned error describes
+    /// the `fail` document which caused it.
+    #[inline]
+    pub fn render_string(&self, width: usize) -> Result<String, RenderError<A>>
+    where
+        A: Clone,
+    {
+        self.render_string_with(&RenderOptions::new(width))
+    }
+
+    /// Renders the document to a `String` using the given `options`.
+    #[inline]
+    pub fn render_string_with(&self, options: &RenderOptions) -> Result<String, RenderError<A>>
+    where
+        A: Clone,
+    {
+        let mut out = render::StringWrite::new();
+        self.render_raw_with(options, &mut out)?;
+        Ok(out.into_string())
+    }
+
+    /// Lays out the document without writing it, so that the result can be written to several
+    /// backends (see `Layout`). If it fails to render, the returned error describes the `fail`
+    /// document which caused it.
+    #[inline]
+    pub fn layout(&self, width: usize) -> Result<Layout<A>, RenderError<A>>
+    where
+        A: Clone,
+    {
+        self.layout_with(&RenderOptions::new(width))
+    }
+
+    /// Lays out the document using the given `options` without writing it.
+    #[inline]
+    pub fn layout_with(&self, options: &RenderOptions) -> Result<Layout<A>, RenderError<A>>
+    where
+        A: Clone,
+    {
+        render::layout(self, options)
     }
 
     /// Returns a value which implements `std::fmt::Display`
@@ -671,7 +1021,13 @@ where
     /// ```
     #[inline]
     pub fn pretty<'d>(&'d self, width: usize) -> PrettyFmt<'a, 'd, T, A> {
-        PrettyFmt { doc: self, width }
+        self.pretty_with(RenderOptions::new(width))
+    }
+
+    /// Returns a value which implements `std::fmt::Display`, rendering with the given `options`
+    #[inline]
+    pub fn pretty_with<'d>(&'d self, options: RenderOptions) -> PrettyFmt<'a, 'd, T, A> {
+        PrettyFmt { doc: self, options }
     }
 }
 
@@ -685,7 +1041,15 @@ where
     where
         W: WriteColor,
     {
-        render::best(self, width, &mut TermColored::new(out))
+        self.render_colored_with(&RenderOptions::new(width), out)
+    }
+
+    #[inline]
+    pub fn render_colored_with<W>(&self, options: &RenderOptions, out: W) -> io::Result<()>
+    where
+        W: WriteColor,
+    {
+        render::best(self, options, &mut TermColored::new(out))
     }
 }
 
@@ -900,6 +1264,17 @@ where
     }
 }
 
+/// Documents track the column they end at, so text with line breaks in it leads to broken layouts.
+/// `multiline_text` should be used for such text instead.
+#[inline]
+fn debug_assert_no_line_breaks(text: &str) {
+    debug_assert!(
+        !text.contains('\n'),
+        "text documents must not contain line breaks, use `multiline_text` instead: {:?}",
+        text
+    );
+}
+
 /// The `DocAllocator` trait abstracts over a type which can allocate (pointers to) `Doc`.
 pub trait DocAllocator<'a, A = ()>
 where
@@ -940,6 +1315,29 @@ where
         DocBuilder(self, Doc::Fail.into())
     }
 
+    /// Fails document rendering immediately like `fail`, reporting `message` in the
+    /// `RenderError` if rendering fails because of it.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator};
+    ///
+    /// let arena = Arena::<&str>::new();
+    /// let doc = arena
+    ///     .text("x")
+    ///     .append(arena.fail_with("unknown type").annotate("type"))
+    ///     .annotate("let");
+    ///
+    /// let error = doc.1.render_string(80).unwrap_err();
+    /// assert_eq!(error.message(), Some("unknown type"));
+    /// assert_eq!(error.annotations(), ["let", "type"]);
+    /// assert_eq!(error.to_string(), "Document failed to render: unknown type");
+    /// ```
+    #[inline]
+    fn fail_with<U: Into<Cow<'a, str>>>(&'a self, message: U) -> DocBuilder<'a, Self, A> {
+        let message: Cow<'a, str> = message.into();
+        DocBuilder(self, Doc::FailWith(message.into()).into())
+    }
+
     /// Allocate a single hardline.
     #[inline]
     fn hardline(&'a self) -> DocBuilder<'a, Self, A> {
@@ -996,25 +1394,33 @@ where
 
     /// Allocate a document containing the text `t.to_string()`.
     ///
-    /// The given text must not contain line breaks.
+    /// The given text must not contain line breaks, which is checked in debug builds.
     #[inline]
     fn as_string<U: fmt::Display>(&'a self, data: U) -> DocBuilder<'a, Self, A> {
         use std::fmt::Write;
         let mut buf = FmtText::Small(SmallText::new());
         write!(buf, "{}", data).unwrap();
         let doc = match buf {
-            FmtText::Small(b) => Doc::SmallText(b),
-            FmtText::Large(b) => Doc::OwnedText(b.into()),
+            FmtText::Small(b) => {
+                debug_assert_no_line_breaks(&b);
+                Doc::SmallText(b)
+            }
+            FmtText::Large(b) => {
+                debug_assert_no_line_breaks(&b);
+                Doc::OwnedText(b.into())
+            }
         };
         DocBuilder(self, doc.into()).with_utf8_len()
     }
 
     /// Allocate a document containing the given text.
     ///
-    /// The given text must not contain line breaks.
+    /// The given text must not contain line breaks, which is checked in debug builds. Use
+    /// `multiline_text` for text which may contain them.
     #[inline]
     fn text<U: Into<Cow<'a, str>>>(&'a self, data: U) -> DocBuilder<'a, Self, A> {
         let data: Cow<_> = data.into();
+        debug_assert_no_line_breaks(&data);
         let doc = if data.is_empty() {
             Doc::Nil.into()
         } else {
@@ -1026,6 +1432,58 @@ where
         DocBuilder(self, doc).with_utf8_len()
     }
 
+    /// Allocate a document containing the given text, which may contain line breaks.
+    ///
+    /// Each line becomes a text document and the line breaks between them become `hardline`s, so
+    /// the lines after the first one are indented by the current nesting. Both `\n` and `\r\n`
+    /// are treated as line breaks.
+    ///
+    /// ```rust
+    /// use pretty::DocAllocator;
+    ///
+    /// let arena = pretty::Arena::<()>::new();
+    /// let doc = arena
+    ///     .text("fn main() {")
+    ///     .append(
+    ///         arena
+    ///             .hardline()
+    ///             .append(arena.multiline_text("/* first\r\n * second */"))
+    ///             .nest(4),
+    ///     )
+    ///     .append(arena.hardline())
+    ///     .append("}");
+    /// assert_eq!(
+    ///     doc.1.pretty(80).to_string(),
+    ///     "fn main() {\n    /* first\n     * second */\n}",
+    /// );
+    /// ```
+    #[inline]
+    fn multiline_text<U: Into<Cow<'a, str>>>(&'a self, data: U) -> DocBuilder<'a, Self, A> {
+        fn strip_cr(line: &str) -> &str {
+            line.strip_suffix('\r').unwrap_or(line)
+        }
+
+        let data: Cow<_> = data.into();
+        if !data.contains('\n') {
+            return self.text(data);
+        }
+        let lines: Vec<Cow<'a, str>> = match data {
+            Cow::Borrowed(s) => s.split('\n').map(|l| strip_cr(l).into()).collect(),
+            Cow::Owned(s) => s
+                .split('\n')
+                .map(|l| strip_cr(l).to_owned().into())
+                .collect(),
+        };
+        let mut doc = self.nil();
+        for (i, line) in lines.into_iter().enumerate() {
+            if i != 0 {
+                doc = doc.append(self.hardline());
+            }
+            doc = doc.append(self.text(line));
+        }
+        doc
+    }
+
     /// Allocate a document concatenating the given documents.
     #[inline]
     fn concat<I>(&'a self, docs: I) -> DocBuilder<'a, Self, A>
@@ -1065,7 +1523,241 @@ where
         result
     }
 
-    /// Allocate a document that acts differently based on the position and page layout
+    /// Creates a builder for a list of the given documents between `open` and `close`,
+    /// separated by `separator` (see `EncloseSep`).
+    #[inline]
+    fn enclose_sep<O, C, S, I>(
+        &'a self,
+        open: O,
+        close: C,
+        separator: S,
+        docs: I,
+    ) -> EncloseSep<'a, Self, A>
+    where
+        Self: Sized,
+        O: Pretty<'a, Self, A>,
+        C: Pretty<'a, Self, A>,
+        S: Pretty<'a, Self, A>,
+        I: IntoIterator,
+        I::Item: Pretty<'a, Self, A>,
+    {
+        EncloseSep::new(
+            open.pretty(self),
+            close.pretty(self),
+            separator.pretty(self),
+            docs.into_iter().map(|doc| doc.pretty(self)).collect(),
+        )
+    }
+
+    /// Creates a builder for a table whose cells are given row by row (see `Table`).
+    #[inline]
+    fn table<R>(&'a self, rows: R) -> Table<'a, Self, A>
+    where
+        Self: Sized,
+        R: IntoIterator,
+        R::Item: IntoIterator,
+        <R::Item as IntoIterator>::Item: Pretty<'a, Self, A>,
+    {
+        Table::new(
+            self,
+            rows.into_iter()
+                .map(|row| row.into_iter().map(|doc| doc.pretty(self)).collect())
+                .collect(),
+        )
+    }
+
+    /// Allocate a document that concatenates the given documents separated by `space`.
+    #[inline]
+    fn hsep<I>(&'a self, docs: I) -> DocBuilder<'a, Self, A>
+    where
+        I: IntoIterator,
+        I::Item: Pretty<'a, Self, A>,
+    {
+        self.intersperse(docs, " ")
+    }
+
+    /// Allocate a document that concatenates the given documents separated by `line`, which puts
+    /// each document on its own line unless grouped.
+    #[inline]
+    fn vsep<I>(&'a self, docs: I) -> DocBuilder<'a, Self, A>
+    where
+        Self: Sized,
+        Self::Doc: Clone,
+        I: IntoIterator,
+        I::Item: Pretty<'a, Self, A>,
+        A: Clone,
+    {
+        self.intersperse(docs, self.line())
+    }
+
+    /// Allocate a document that concatenates the given documents separated by `line`, and puts
+    /// them on a single line separated by spaces if they fit (`vsep` grouped).
+    ///
+    /// ```
+    /// use pretty::DocAllocator;
+    ///
+    /// let arena = pretty::Arena::<()>::new();
+    /// let doc = arena.text("prefix ").append(arena.sep(["text", "to", "lay", "out"]).align());
+    /// assert_eq!(doc.1.pretty(80).to_string(), "prefix text to lay out");
+    /// assert_eq!(doc.1.pretty(20).to_string(), "prefix text\n       to\n       lay\n       out");
+    /// ```
+    #[inline]
+    fn sep<I>(&'a self, docs: I) -> DocBuilder<'a, Self, A>
+    where
+        Self: Sized,
+        Self::Doc: Clone,
+        I: IntoIterator,
+        I::Item: Pretty<'a, Self, A>,
+        A: Clone,
+    {
+        self.vsep(docs).group()
+    }
+
+    /// Allocate a document that concatenates the given documents without separating them (the
+    /// same as `concat`).
+    #[inline]
+    fn hcat<I>(&'a self, docs: I) -> DocBuilder<'a, Self, A>
+    where
+        I: IntoIterator,
+        I::Item: Pretty<'a, Self, A>,
+    {
+        self.concat(docs)
+    }
+
+    /// Allocate a document that concatenates the given documents separated by `line_`, which puts
+    /// each document on its own line unless grouped.
+    #[inline]
+    fn vcat<I>(&'a self, docs: I) -> DocBuilder<'a, Self, A>
+    where
+        Self: Sized,
+        Self::Doc: Clone,
+        I: IntoIterator,
+        I::Item: Pretty<'a, Self, A>,
+        A: Clone,
+    {
+        self.intersperse(docs, self.line_())
+    }
+
+    /// Allocate a document that concatenates the given documents separated by `line_`, and puts
+    /// them on a single line without separators if they fit (`vcat` grouped).
+    #[inline]
+    fn cat<I>(&'a self, docs: I) -> DocBuilder<'a, Self, A>
+    where
+        Self: Sized,
+        Self::Doc: Clone,
+        I: IntoIterator,
+        I::Item: Pretty<'a, Self, A>,
+        A: Clone,
+    {
+        self.vcat(docs).group()
+    }
+
+    /// Appends `punctuation` to every document except the last.
+    ///
+    /// ```
+    /// use pretty::DocAllocator;
+    ///
+    /// let arena = pretty::Arena::<()>::new();
+    /// let doc = arena.sep(arena.punctuate(["a", "b", "c"], ",")).parens();
+    /// assert_eq!(doc.1.pretty(80).to_string(), "(a, b, c)");
+    /// assert_eq!(doc.1.pretty(4).to_string(), "(a,\nb,\nc)");
+    /// ```
+    ///
+    /// NOTE: The punctuation type, `P` may need to be cloned. Consider using cheaply cloneable
+    /// ptr like `RefDoc` or `RcDoc`
+    #[inline]
+    fn punctuate<I, P>(&'a self, docs: I, punctuation: P) -> Vec<DocBuilder<'a, Self, A>>
+    where
+        I: IntoIterator,
+        I::Item: Pretty<'a, Self, A>,
+        P: Pretty<'a, Self, A> + Clone,
+    {
+        let mut docs = docs.into_iter().peekable();
+        let mut result = Vec::new();
+        while let Some(doc) = docs.next() {
+            let doc = doc.pretty(self);
+            result.push(match docs.peek() {
+                Some(_) => doc.append(punctuation.clone()),
+                None => doc,
+            });
+        }
+        result
+    }
+
+    /// Allocate a document that concatenates the given documents, putting as many of them on each
+    /// line as fit. Unlike in a `group`, where either all or none of the line breaks are broken,
+    /// each `separator` (usually containing a `line` or `line_`) is only broken if the document
+    /// after it does not fit on the rest of the line when laid out on a single line.
+    ///
+    /// NOTE: The separator type, `S` may need to be cloned. Consider using cheaply cloneable ptr
+    /// like `RefDoc` or `RcDoc`
+    ///
+    /// ```
+    /// use pretty::DocAllocator;
+    ///
+    /// let arena = pretty::Arena::<()>::new();
+    /// let names = ["alpha", "beta", "gamma", "delta", "epsilon"];
+    /// let doc = arena
+    ///     .text("use greek::{")
+    ///     .append(arena.fill(names, arena.text(",").append(arena.line())).nest(4))
+    ///     .append("};");
+    /// assert_eq!(
+    ///     doc.1.pretty(24).to_string(),
+    ///     "use greek::{alpha, beta,\n    gamma, delta,\n    epsilon};",
+    /// );
+    /// ```
+    #[inline]
+    fn fill<I, S>(&'a self, docs: I, separator: S) -> DocBuilder<'a, Self, A>
+    where
+        I: IntoIterator,
+        I::Item: Pretty<'a, Self, A>,
+        S: Pretty<'a, Self, A> + Clone,
+    {
+        let mut result = self.nil();
+        let mut iter = docs.into_iter();
+
+        if let Some(first) = iter.next() {
+            result = result.append(first);
+
+            // Grouping each document with the separator before it breaks the separator unless
+            // the whole document fits
+            for doc in iter {
+                result = result.append(separator.clone().pretty(self).append(doc).group());
+            }
+        }
+
+        result
+    }
+
+    /// Allocate a document that concatenates the given documents separated by `line`, putting as
+    /// many of them on each line as fit (see `fill`).
+    #[inline]
+    fn fill_sep<I>(&'a self, docs: I) -> DocBuilder<'a, Self, A>
+    where
+        Self: Sized,
+        Self::Doc: Clone,
+        I: IntoIterator,
+        I::Item: Pretty<'a, Self, A>,
+        A: Clone,
+    {
+        self.fill(docs, self.line())
+    }
+
+    /// Allocate a document that concatenates the given documents separated by `line_`, putting as
+    /// many of them on each line as fit (see `fill`).
+    #[inline]
+    fn fill_cat<I>(&'a self, docs: I) -> DocBuilder<'a, Self, A>
+    where
+        Self: Sized,
+        Self::Doc: Clone,
+        I: IntoIterator,
+        I::Item: Pretty<'a, Self, A>,
+        A: Clone,
+    {
+        self.fill(docs, self.line_())
+    }
+
+    /// Allocate a document that acts differently based on the position and page layout
     ///
     /// ```rust
     /// use pretty::DocAllocator;
@@ -1273,7 +1965,7 @@ where
             let DocBuilder(allocator, _) = self;
             DocBuilder(
                 allocator,
-                Doc::RenderLen(display_width, self.into_doc()).into(),
+                Doc::MeasuredText(display_width, self.into_doc()).into(),
             )
         }
     }
@@ -1394,10 +2086,119 @@ where
         DocBuilder(allocator, doc.into())
     }
 
-    /// Lays out `self` so with the nesting level set to the current column
+    /// Replaces the groups and annotations which are nested more than `max_depth` groups and
+    /// annotations deep with `placeholder`, like `pprint` with a `depth` does in Python.
     ///
-    /// NOTE: The doc pointer type, `D` may need to be cloned. Consider using cheaply cloneable ptr
-    /// like `RefDoc` or `RcDoc`
+    /// This copies the document, so deeply nested data can be printed with its regular `Pretty`
+    /// implementation and be elided afterwards.
+    ///
+    /// ```rust
+    /// use pretty::{Arena, DocAllocator, DocBuilder};
+    ///
+    /// fn list<'a>(arena: &'a Arena<'a>, depth: usize) -> DocBuilder<'a, Arena<'a>> {
+    ///     let items = (0..depth).map(|_| list(arena, depth - 1));
+    ///     arena
+    ///         .intersperse(items, arena.text(",").append(arena.line()))
+    ///         .brackets()
+    ///         .group()
+    /// }
+    ///
+    /// let arena = Arena::new();
+    /// let doc = list(&arena, 3).elide_depth(2, "[…]");
+    /// assert_eq!(doc.1.pretty(80).to_string(), "[[[…], […]], [[…], […]], [[…], […]]]");
+    /// ```
+    pub fn elide_depth(
+        self,
+        max_depth: usize,
+        placeholder: impl Into<Cow<'a, str>>,
+    ) -> DocBuilder<'a, D, A>
+    where
+        A: Clone,
+    {
+        let DocBuilder(allocator, this) = self;
+        let doc = elide::Elide::new(allocator, max_depth, placeholder.into()).doc(&this, 0);
+        DocBuilder(allocator, BuildDoc::DocPtr(doc))
+    }
+
+    /// Shortens a text document which is wider than `max_width` columns, so that it ends with
+    /// `ellipsis` and fits in `max_width` columns. Text is only cut between grapheme clusters, and
+    /// an `ellipsis` which is wider than `max_width` is cut as well.
+    ///
+    /// The width is measured with the default `UnicodeWidth`, use `truncate_with` to measure it
+    /// like a custom `RenderOptions::width_measure` does. Documents other than text are returned
+    /// as is.
+    ///
+    /// ```rust
+    /// use pretty::DocAllocator;
+    ///
+    /// let arena = pretty::Arena::<()>::new();
+    /// let doc = arena.text("https://example.com/a/long/path").truncate(16, "…");
+    /// assert_eq!(doc.1.pretty(80).to_string(), "https://example…");
+    /// let doc = arena.text("日本語").truncate(4, "…");
+    /// assert_eq!(doc.1.pretty(80).to_string(), "日…");
+    /// ```
+    pub fn truncate(
+        self,
+        max_width: usize,
+        ellipsis: impl Into<Cow<'a, str>>,
+    ) -> DocBuilder<'a, D, A> {
+        self.truncate_with(max_width, ellipsis, &UnicodeWidth::new())
+    }
+
+    /// Like `truncate`, but measures the text with `measure`.
+    pub fn truncate_with(
+        self,
+        max_width: usize,
+        ellipsis: impl Into<Cow<'a, str>>,
+        measure: &dyn WidthMeasure,
+    ) -> DocBuilder<'a, D, A> {
+        let text = match *self.1 {
+            Doc::OwnedText(ref s) => &s[..],
+            Doc::BorrowedText(s) => s,
+            Doc::SmallText(ref s) => s,
+            Doc::RenderLen(_, ref doc) | Doc::MeasuredText(_, ref doc) => match **doc {
+                Doc::OwnedText(ref s) => &s[..],
+                Doc::BorrowedText(s) => s,
+                Doc::SmallText(ref s) => s,
+                _ => return self,
+            },
+            _ => return self,
+        };
+        if measure.width(text, 0) <= max_width {
+            return self;
+        }
+
+        // The end of the longest prefix of `s` which, followed by `rest`, fits in `max_width`
+        let prefix = |s: &str, rest: &str| {
+            let mut width = 0;
+            let mut end = 0;
+            for (i, grapheme) in s.grapheme_indices(true) {
+                let grapheme_width = measure.width(grapheme, width);
+                if width + grapheme_width + measure.width(rest, width + grapheme_width) > max_width
+                {
+                    break;
+                }
+                width += grapheme_width;
+                end = i + grapheme.len();
+            }
+            end
+        };
+        let ellipsis = ellipsis.into();
+        if measure.width(&ellipsis, 0) > max_width {
+            let ellipsis = match ellipsis {
+                Cow::Borrowed(s) => Cow::Borrowed(&s[..prefix(s, "")]),
+                Cow::Owned(mut s) => {
+                    s.truncate(prefix(&s, ""));
+                    Cow::Owned(s)
+                }
+            };
+            return self.0.text(ellipsis);
+        }
+        let truncated = format!("{}{}", &text[..prefix(text, &ellipsis)], ellipsis);
+        self.0.text(truncated)
+    }
+
+    /// Lays out `self` so with the nesting level set to the current column
     ///
     /// ```rust
     /// use pretty::{docs, DocAllocator};
@@ -1414,24 +2215,16 @@ where
     /// assert_eq!(doc.1.pretty(80).to_string(), "lorem ipsum\n      dolor\nnext");
     /// ```
     #[inline]
-    pub fn align(self) -> DocBuilder<'a, D, A>
-    where
-        DocBuilder<'a, D, A>: Clone,
-    {
-        let allocator = self.0;
-        allocator.column(move |col| {
-            let self_ = self.clone();
-            allocator
-                .nesting(move |nest| self_.clone().nest(col as isize - nest as isize).into_doc())
-                .into_doc()
-        })
+    pub fn align(self) -> DocBuilder<'a, D, A> {
+        if let Doc::Nil = &*self.1 {
+            return self;
+        }
+        let DocBuilder(allocator, this) = self;
+        DocBuilder(allocator, Doc::Align(allocator.alloc_cow(this)).into())
     }
 
     /// Lays out `self` with a nesting level set to the current level plus `adjust`.
     ///
-    /// NOTE: The doc pointer type, `D` may need to be cloned. Consider using cheaply cloneable ptr
-    /// like `RefDoc` or `RcDoc`
-    ///
     /// ```rust
     /// use pretty::DocAllocator;
     ///
@@ -1444,18 +2237,12 @@ where
     /// );
     /// ```
     #[inline]
-    pub fn hang(self, adjust: isize) -> DocBuilder<'a, D, A>
-    where
-        DocBuilder<'a, D, A>: Clone,
-    {
+    pub fn hang(self, adjust: isize) -> DocBuilder<'a, D, A> {
         self.nest(adjust).align()
     }
 
     /// Indents `self` by `adjust` spaces from the current cursor position
     ///
-    /// NOTE: The doc pointer type, `D` may need to be cloned. Consider using cheaply cloneable ptr
-    /// like `RefDoc` or `RcDoc`
-    ///
     /// ```rust
     /// use pretty::DocAllocator;
     ///
@@ -1472,10 +2259,7 @@ where
     /// );
     /// ```
     #[inline]
-    pub fn indent(self, adjust: usize) -> DocBuilder<'a, D, A>
-    where
-        DocBuilder<'a, D, A>: Clone,
-    {
+    pub fn indent(self, adjust: usize) -> DocBuilder<'a, D, A> {
         let spaces = {
             use crate::render::SPACES;
             let DocBuilder(allocator, _) = self;
@@ -1522,6 +2306,76 @@ where
         })
     }
 
+    /// Pads `self` with `pad`, which should be one column wide, on the right until it is `width`
+    /// columns wide. Nothing is added if it is already as wide or wider.
+    ///
+    /// ```rust
+    /// use pretty::DocAllocator;
+    ///
+    /// let arena = pretty::Arena::<()>::new();
+    /// let field = |name: &'static str, value: &'static str| {
+    ///     arena.text(name).fill_to(8, ' ').append(": ").append(value)
+    /// };
+    /// let doc = field("name", "pretty")
+    ///     .append(arena.hardline())
+    ///     .append(field("version", "0.12"))
+    ///     .append(arena.hardline())
+    ///     .append(field("license", "MIT"));
+    /// assert_eq!(
+    ///     doc.1.pretty(80).to_string(),
+    ///     "name    : pretty\nversion : 0.12\nlicense : MIT",
+    /// );
+    /// ```
+    #[inline]
+    pub fn fill_to(self, width: usize, pad: char) -> DocBuilder<'a, D, A>
+    where
+        BuildDoc<'a, D::Doc, A>: Clone,
+    {
+        let allocator = self.0;
+        self.width(move |actual| pad_to(allocator, width, actual, pad).into_doc())
+    }
+
+    /// Pads `self` like `fill_to`, but if `self` is wider than `width` columns a line break is
+    /// added instead (which acts like `nil` if grouped on a single line), so that what follows
+    /// starts on a new line at the column it would have been padded to.
+    ///
+    /// ```rust
+    /// use pretty::DocAllocator;
+    ///
+    /// let arena = pretty::Arena::<()>::new();
+    /// let field = |name: &'static str, ty: &'static str| {
+    ///     arena.text(name).fill_break(6, ' ').append(":: ").append(ty)
+    /// };
+    /// let doc = field("empty", "Doc")
+    ///     .append(arena.hardline())
+    ///     .append(field("nest", "Int -> Doc -> Doc"))
+    ///     .append(arena.hardline())
+    ///     .append(field("linebreak", "Doc"))
+    ///     .align();
+    /// assert_eq!(
+    ///     arena.text("let ").append(doc).1.pretty(80).to_string(),
+    ///     "let empty :: Doc\n    nest  :: Int -> Doc -> Doc\n    linebreak\n          :: Doc",
+    /// );
+    /// ```
+    #[inline]
+    pub fn fill_break(self, width: usize, pad: char) -> DocBuilder<'a, D, A>
+    where
+        BuildDoc<'a, D::Doc, A>: Clone,
+    {
+        let allocator = self.0;
+        self.width(move |actual| {
+            if actual > width as isize {
+                allocator
+                    .hardline()
+                    .append(pad_to(allocator, width, 0, ' '))
+                    .flat_alt(allocator.nil())
+                    .into_doc()
+            } else {
+                pad_to(allocator, width, actual, pad).into_doc()
+            }
+        })
+    }
+
     /// Puts `self` between `before` and `after`
     #[inline]
     pub fn enclose<E, F>(self, before: E, after: F) -> DocBuilder<'a, D, A>
@@ -1572,6 +2426,23 @@ where
     }
 }
 
+/// The padding which makes a document which is `actual` columns wide `width` columns wide
+fn pad_to<'a, D, A>(
+    allocator: &'a D,
+    width: usize,
+    actual: isize,
+    pad: char,
+) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+{
+    let missing = (width as isize).saturating_sub(actual).max(0) as usize;
+    if missing == 0 {
+        return allocator.nil();
+    }
+    allocator.text(pad.to_string().repeat(missing))
+}
+
 /// Newtype wrapper for `&Doc`
 pub struct RefDoc<'a, A = ()>(pub &'a Doc<'a, RefDoc<'a, A>, A>);
 
@@ -1724,6 +2595,7 @@ impl<'a, A> DocAllocator<'a, A> for Arena<'a, A> {
 }
 
 #[cfg(test)]
+#[allow(clippy::legacy_numeric_constants)]
 mod tests {
     use super::*;
 
@@ -1768,6 +2640,10 @@ mod tests {
     }
 
     #[test]
+    #[cfg_attr(
+        debug_assertions,
+        should_panic(expected = "must not contain line breaks")
+    )]
     fn newline_in_text() {
         let doc: BoxDoc<()> = BoxDoc::group(
             BoxDoc::text("test").append(
@@ -1780,6 +2656,24 @@ mod tests {
         test!(5, doc, "test\n    \"test\n     test\"");
     }
 
+    #[test]
+    fn multiline_text() {
+        let doc: BoxDoc<()> = BoxDoc::text("x =").append(
+            BoxDoc::line()
+                .append(BoxDoc::multiline_text(String::from("\"a\n\nb\r\n\"")))
+                .nest(4),
+        );
+
+        test!(5, doc, "x =\n    \"a\n\n    b\n    \"");
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "must not contain line breaks")]
+    fn line_break_in_text() {
+        BoxDoc::<()>::text("a\nb");
+    }
+
     #[test]
     fn forced_newline() {
         let doc: BoxDoc<()> = BoxDoc::group(
@@ -1823,6 +2717,701 @@ mod tests {
         test!(100, doc.append(arena.hardline()).1, "x y\n");
     }
 
+    #[test]
+    fn no_trailing_whitespace_on_empty_lines() {
+        let doc: RcDoc<()> = RcDoc::text("{")
+            .append(
+                RcDoc::hardline()
+                    .append("a")
+                    .append(RcDoc::hardline())
+                    .append(RcDoc::hardline())
+                    .append("b")
+                    .append(RcDoc::hardline())
+                    .nest(4),
+            )
+            .append("}");
+
+        test!(doc, "{\n    a\n\n    b\n}");
+    }
+
+    #[test]
+    fn no_trailing_spaces_before_newlines() {
+        let arena = Arena::<()>::new();
+        let doc = arena
+            .text("a")
+            .append(arena.line())
+            .group()
+            .append(arena.hardline())
+            .append(arena.text("b  ").annotate(()))
+            .append(arena.hardline())
+            .append(arena.text(" ").append("c"))
+            .append(" ");
+
+        test!(doc, "a\nb\n c");
+
+        let mut s = String::new();
+        doc.render_raw(80, &mut TestWriter::new(FmtWrite::new(&mut s)))
+            .unwrap();
+        assert_eq!(s, "a\n[b]\n c");
+
+        let mut stream = StreamRender::new(RenderOptions::new(80), render::StringWrite::new());
+        stream.push(&doc).unwrap();
+        assert_eq!(stream.finish().unwrap().into_string(), "a\nb\n c");
+    }
+
+    #[test]
+    fn crlf_newline() {
+        let doc: RcDoc<()> = RcDoc::text("a")
+            .append(RcDoc::line().append("b").nest(2))
+            .append(RcDoc::hardline())
+            .append("c")
+            .annotate(());
+
+        let options = RenderOptions::new(1).newline("\r\n");
+        let mut s = String::new();
+        doc.render_fmt_with(&options, &mut s).unwrap();
+        assert_eq!(s, "a\r\n  b\r\nc");
+
+        let mut s = String::new();
+        doc.render_raw_with(&options, &mut TestWriter::new(FmtWrite::new(&mut s)))
+            .unwrap();
+        assert_eq!(s, "[a\r\n  b\r\nc]");
+    }
+
+    #[test]
+    fn annotations_start_after_indentation() {
+        let doc: RcDoc<()> = RcDoc::text("a")
+            .append(
+                RcDoc::hardline()
+                    .append(RcDoc::text("b").annotate(()))
+                    .append(RcDoc::hardline().annotate(()))
+                    .append(RcDoc::hardline())
+                    .nest(2),
+            )
+            .append(RcDoc::text("c").annotate(()));
+
+        let mut s = String::new();
+        doc.render_raw(80, &mut TestWriter::new(FmtWrite::new(&mut s)))
+            .unwrap();
+        assert_eq!(s, "a\n  [b][\n]\n[c]");
+    }
+
+    #[test]
+    fn elide_depth() {
+        let doc: RcDoc<()> = RcDoc::text("a")
+            .append(
+                RcDoc::line()
+                    .append(RcDoc::text("b").annotate(()))
+                    .append(RcDoc::column(|_| {
+                        RcDoc::text("c").annotate(()).annotate(())
+                    }))
+                    .group(),
+            )
+            .annotate(());
+
+        let check = |max_depth, expected: &str| {
+            let doc = doc.clone().elide_depth(max_depth, "..");
+            let mut s = String::new();
+            doc.render_raw(80, &mut TestWriter::new(FmtWrite::new(&mut s)))
+                .unwrap();
+            assert_eq!(s, expected);
+        };
+        check(0, "..");
+        check(1, "[a..]");
+        check(2, "[a ....]");
+        check(3, "[a [b][..]]");
+        check(4, "[a [b][[c]]]");
+    }
+
+    #[test]
+    fn elide_depth_deep_list() {
+        let arena = Arena::<()>::new();
+        let doc = (0..100_000).fold(arena.nil(), |doc, _| doc.append("a"));
+        let doc = doc.elide_depth(1, "..");
+        let mut s = String::new();
+        doc.render_raw(80, &mut TestWriter::new(FmtWrite::new(&mut s)))
+            .unwrap();
+        assert_eq!(s, "a".repeat(100_000));
+    }
+
+    #[test]
+    fn truncate() {
+        let arena = Arena::<()>::new();
+        test!(arena.text("abcdef").truncate(6, "…"), "abcdef");
+        test!(arena.text("abcdefg").truncate(6, "…"), "abcde…");
+        test!(arena.text("abcdefg").truncate(6, "..."), "abc...");
+        test!(arena.text("abcdefg").truncate(2, "..."), "..");
+        test!(arena.text("abcdefg").truncate(0, "..."), "");
+        test!(arena.text("abc").truncate(1, "日"), "");
+        test!(
+            arena.text("ae\u{301}e\u{301}e").truncate(3, "…"),
+            "ae\u{301}…"
+        );
+        test!(arena.text("日本語").truncate(5, "…"), "日本…");
+        test!(
+            arena
+                .text("a\tb\tc")
+                .truncate_with(8, "…", &UnicodeWidth::new().tab_width(4)),
+            "a\tb…"
+        );
+        test!(
+            RcDoc::<()>::text("abcdefg").truncate(4, "…").append(";"),
+            "abc…;"
+        );
+    }
+
+    #[test]
+    fn max_lines() {
+        let arena = Arena::<()>::new();
+        let doc = arena
+            .text("a")
+            .append(
+                arena
+                    .hardline()
+                    .append(arena.text("b").annotate(()))
+                    .append(arena.hardline())
+                    .append("c")
+                    .nest(2),
+            )
+            .annotate(());
+
+        for options in [
+            RenderOptions::new(80),
+            RenderOptions::new(80).optimal(DefaultCost::new()),
+        ] {
+            let mut s = String::new();
+            doc.render_raw_with(
+                &options.clone().max_lines(2),
+                &mut TestWriter::new(FmtWrite::new(&mut s)),
+            )
+            .unwrap();
+            assert_eq!(s, "[a\n  [b]\n…]");
+
+            let options = options.max_lines(0).elision_marker("...");
+            assert_eq!(doc.1.pretty_with(options).to_string(), "a\n...");
+        }
+        assert_eq!(
+            doc.1
+                .pretty_with(RenderOptions::new(80).max_lines(3))
+                .to_string(),
+            "a\n  b\n  c"
+        );
+    }
+
+    #[test]
+    fn max_lines_without_anything_cut() {
+        let arena = Arena::<()>::new();
+        let doc = arena
+            .text("a")
+            .append(arena.hardline())
+            .append(arena.text("b").annotate(()))
+            .append(arena.hardline().annotate(()))
+            .append(arena.hardline().nest(2));
+
+        for options in [
+            RenderOptions::new(80),
+            RenderOptions::new(80).optimal(DefaultCost::new()),
+        ] {
+            // Only empty lines are left out, so there is nothing to mark
+            let options = options.max_lines(2);
+            let mut s = String::new();
+            doc.render_raw_with(&options, &mut TestWriter::new(FmtWrite::new(&mut s)))
+                .unwrap();
+            assert_eq!(s, "a\n[b][]");
+
+            let doc = doc.clone().append(" ").append("c");
+            assert_eq!(doc.1.pretty_with(options).to_string(), "a\nb\n…");
+        }
+    }
+
+    #[test]
+    fn max_bytes() {
+        let arena = Arena::<()>::new();
+        let doc = arena
+            .text("äö")
+            .append(arena.hardline().append("bb").nest(4));
+
+        let check = |max_bytes, expected: &str| {
+            let options = RenderOptions::new(80).max_bytes(max_bytes);
+            assert_eq!(doc.1.pretty_with(options).to_string(), expected);
+        };
+        check(0, "…");
+        check(3, "ä…");
+        check(4, "äö…");
+        check(5, "äö\n…");
+        check(8, "äö\n…");
+        check(11, "äö\n    bb");
+    }
+
+    #[test]
+    fn nested_unions_are_tried_once() {
+        let arena = Arena::<()>::new();
+        // Every union contains the next one on both sides, so trying each side of every union
+        // would take exponential time
+        let mut doc = arena.text("a");
+        for _ in 0..32 {
+            doc = doc.clone().append("too long").union(doc);
+        }
+
+        let mut stats = LayoutStats::default();
+        let mut s = String::new();
+        doc.render_raw_traced(
+            &RenderOptions::new(4),
+            &mut FmtWrite::new(&mut s),
+            &mut stats,
+        )
+        .unwrap();
+        assert_eq!(s, "a");
+        assert_eq!(stats.unions, 32);
+    }
+
+    #[test]
+    fn sequences() {
+        let docs = || vec!["a", "b", "c"];
+
+        test!(RcDoc::<()>::hsep(docs()), "a b c");
+        test!(RcDoc::<()>::vsep(docs()), "a\nb\nc");
+        test!(RcDoc::<()>::vsep(docs()).group(), "a b c");
+        test!(RcDoc::<()>::sep(docs()), "a b c");
+        test!(4, RcDoc::<()>::sep(docs()), "a\nb\nc");
+        test!(RcDoc::<()>::hcat(docs()), "abc");
+        test!(RcDoc::<()>::vcat(docs()), "a\nb\nc");
+        test!(RcDoc::<()>::cat(docs()), "abc");
+        test!(2, RcDoc::<()>::cat(docs()), "a\nb\nc");
+        test!(RcDoc::<()>::hsep(Vec::<RcDoc<()>>::new()), "");
+
+        let punctuated = RcDoc::<()>::punctuate(docs(), ";");
+        assert_eq!(punctuated.len(), 3);
+        test!(RcDoc::<()>::hsep(punctuated), "a; b; c");
+        assert!(RcDoc::<()>::punctuate(Vec::<RcDoc<()>>::new(), ";").is_empty());
+    }
+
+    #[test]
+    fn fill() {
+        let arena = Arena::<()>::new();
+        let call = |name: &'static str| {
+            arena
+                .text(name)
+                .append("(")
+                .append(arena.line_().append("x, y").nest(4))
+                .append(arena.line_())
+                .append(")")
+                .group()
+        };
+        let doc = arena
+            .text("f(")
+            .append(
+                arena
+                    .fill(
+                        vec![call("a"), call("bbbbbb"), call("c"), call("d")],
+                        arena.text(",").append(arena.line()),
+                    )
+                    .nest(2),
+            )
+            .append(")");
+
+        test!(20, doc, "f(a(x, y),\n  bbbbbb(x, y),\n  c(x, y), d(x, y))");
+        // `d` is moved to the next line rather than broken
+        test!(
+            18,
+            doc,
+            "f(a(x, y),\n  bbbbbb(x, y),\n  c(x, y),\n  d(x, y))"
+        );
+        test!(
+            10,
+            doc,
+            "f(a(x, y),\n  bbbbbb(\n      x, y\n  ),\n  c(x, y),\n  d(x, y))"
+        );
+
+        let doc: RcDoc<()> = RcDoc::fill_cat(vec!["a", "b", "c", "d"]);
+        test!(2, doc, "ab\ncd");
+
+        let doc: RcDoc<()> = RcDoc::fill_sep(vec!["a", "b", "c", "d"]);
+        test!(3, doc, "a b\nc d");
+    }
+
+    #[test]
+    fn fill_to() {
+        let doc: RcDoc<()> = RcDoc::text("ab").fill_to(5, '.').append("|");
+        test!(doc, "ab...|");
+
+        let doc: RcDoc<()> = RcDoc::text("abcdef").fill_to(5, '.').append("|");
+        test!(doc, "abcdef|");
+
+        // Wide characters are measured by their width
+        let doc: RcDoc<()> = RcDoc::text("日本").fill_to(6, '-').append("|");
+        test!(doc, "日本--|");
+
+        let doc: RcDoc<()> = RcDoc::text("abcdef").fill_break(4, ' ').append("|").nest(2);
+        test!(doc, "abcdef\n      |");
+        test!(doc.clone().group(), "abcdef|");
+    }
+
+    #[test]
+    fn deeply_nested_unions() {
+        let arena = Arena::<()>::new();
+        let mut doc = arena.text("a");
+        for _ in 0..100_000 {
+            doc = doc.union(arena.text("b"));
+        }
+
+        test!(doc, "a");
+    }
+
+    #[test]
+    fn unions_with_hardlines() {
+        let arena = Arena::<()>::new();
+        let doc = arena.hardline().union(arena.nil()).append(arena.hardline());
+        for width in [0, 1, 80] {
+            test!(width, doc, "\n\n");
+            let mut s = String::new();
+            doc.render_raw(width, &mut s).unwrap();
+            assert_eq!(s, "\n\n");
+        }
+
+        let inner = arena
+            .text("ab")
+            .append(arena.hardline())
+            .union(arena.hardline().append("c"));
+        let doc = inner
+            .append(arena.hardline())
+            .union(arena.hardline().append(arena.hardline().union(arena.nil())))
+            .append(arena.hardline())
+            .append("d");
+        for (width, expected) in [(0, "\n\n\nd"), (1, "\nc\n\nd"), (80, "ab\n\n\nd")] {
+            test!(width, doc, expected);
+            let mut s = String::new();
+            doc.render_raw(width, &mut s).unwrap();
+            assert_eq!(s, expected);
+        }
+    }
+
+    #[test]
+    fn rollback_unions() {
+        let arena = Arena::<&str>::new();
+        let item = |i: usize| {
+            let name = arena.text(format!("item{}", i)).annotate("name");
+            name.clone().append(" = a long value").union(
+                name.append(" =")
+                    .append(arena.line())
+                    .append(i.to_string())
+                    .group(),
+            )
+        };
+        let doc = arena
+            .text("[")
+            .append(arena.intersperse((0..4).map(item), arena.text(",").append(arena.line())))
+            .append("]")
+            .nest(2)
+            .union(arena.text("[..]"));
+
+        let broken = "[item0 =\n  0,\n  item1 =\n  1,\n  item2 =\n  2,\n  item3 =\n  3]";
+        for (options, expected) in [
+            (RenderOptions::new(6), "[..]"),
+            (RenderOptions::new(10), broken),
+            (RenderOptions::new(10).max_lines(2), "[item0 =\n  0,\n…"),
+            (
+                RenderOptions::new(80),
+                "[item0 = a long value,\n  item1 = a long value,\n  item2 = a long value,\n  item3 = a long value]",
+            ),
+        ] {
+            // Buffers the unions
+            let mut s = String::new();
+            doc.render_raw_with(&options, &mut FmtWrite::new(&mut s))
+                .unwrap();
+            assert_eq!(s, expected);
+
+            // Rolls the output back
+            let mut s = String::new();
+            doc.render_raw_with(&options, &mut s).unwrap();
+            assert_eq!(s, expected);
+
+            let mut bytes = Vec::new();
+            doc.render_raw_with(&options, &mut bytes).unwrap();
+            assert_eq!(String::from_utf8(bytes).unwrap(), expected);
+
+            let layout = doc.layout_with(&options).unwrap();
+            assert_eq!(layout.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn layout_stats() {
+        let arena = Arena::<()>::new();
+        let doc = arena
+            .text("aaaa")
+            .union(arena.text("b"))
+            .append(arena.column(|column| arena.as_string(column).into_doc()))
+            .append(arena.line())
+            .append("c")
+            .group();
+
+        let mut stats = LayoutStats::default();
+        let mut s = String::new();
+        doc.render_raw_traced(
+            &RenderOptions::new(3),
+            &mut FmtWrite::new(&mut s),
+            &mut stats,
+        )
+        .unwrap();
+        assert_eq!(s, "b1\nc");
+        assert_eq!(stats.fitting_calls, 1);
+        assert_eq!(stats.unions, 1);
+        assert_eq!(stats.union_backtracks, 1);
+        assert_eq!(stats.max_union_buffer, 1);
+        assert_eq!(stats.column_fns, 2);
+        assert!(stats.fitting_docs > 0);
+        assert!(stats.max_stack_depth > 1);
+        assert!(stats.commands >= stats.max_stack_depth);
+    }
+
+    #[test]
+    fn overflows() {
+        let arena = Arena::<&str>::new();
+        let doc = arena
+            .text("fn")
+            .append(
+                arena
+                    .hardline()
+                    .append("abc")
+                    .append(arena.text("defgh").annotate("long"))
+                    .append("ij")
+                    .append(arena.hardline())
+                    .append("ok")
+                    .nest(4),
+            )
+            .append(arena.hardline())
+            .append(arena.text("12345678").annotate("number"))
+            .annotate("fn");
+
+        let mut s = String::new();
+        let overflows = doc.1.render_fmt_overflows(8, &mut s).unwrap();
+        assert_eq!(s, "fn\n    abcdefghij\n    ok\n12345678");
+        assert_eq!(
+            overflows,
+            [Overflow {
+                line: 1,
+                width: 14,
+                column: 7,
+                text: "defgh".into(),
+                annotations: vec!["fn", "long"],
+            }]
+        );
+
+        let overflows = doc.1.render_fmt_overflows(7, &mut String::new()).unwrap();
+        assert_eq!(overflows.len(), 2);
+        assert_eq!(overflows[1].line, 3);
+        assert_eq!(overflows[1].annotations, ["fn", "number"]);
+    }
+
+    #[test]
+    fn annotation_spans() {
+        let doc: RcDoc<&str> = RcDoc::text("let")
+            .append(
+                RcDoc::line()
+                    .append(RcDoc::text("x").annotate("name"))
+                    .append(" = ")
+                    .append(RcDoc::text("ÅÄÖ").annotate("value"))
+                    .nest(4)
+                    .annotate("binding"),
+            )
+            .group();
+
+        let mut s = String::new();
+        let spans = doc.render_fmt_spans(5, &mut s).unwrap();
+        assert_eq!(s, "let\n    x = ÅÄÖ");
+
+        let span = |line, column, offset| Position {
+            line,
+            column,
+            offset,
+        };
+        assert_eq!(
+            spans,
+            [
+                Span {
+                    annotation: "binding",
+                    start: span(0, 3, 3),
+                    end: span(1, 14, 18),
+                },
+                Span {
+                    annotation: "name",
+                    start: span(1, 4, 8),
+                    end: span(1, 5, 9),
+                },
+                Span {
+                    annotation: "value",
+                    start: span(1, 8, 12),
+                    end: span(1, 14, 18),
+                },
+            ]
+        );
+    }
+
+    struct CursorLog(Vec<String>);
+
+    impl Render for CursorLog {
+        type Error = fmt::Error;
+
+        fn write_str(&mut self, s: &str) -> Result<usize, fmt::Error> {
+            Ok(s.len())
+        }
+
+        fn write_text(&mut self, s: &str, cursor: Cursor) -> fmt::Result {
+            self.0.push(format!("{:?} {:?}", s, cursor));
+            Ok(())
+        }
+
+        fn newline(&mut self, cursor: Cursor) -> fmt::Result {
+            self.0.push(format!("newline {:?}", cursor));
+            Ok(())
+        }
+
+        fn fail_doc(&self) -> fmt::Error {
+            fmt::Error
+        }
+    }
+
+    impl RenderAnnotated<'_, ()> for CursorLog {
+        fn push_annotation(&mut self, _: &()) -> fmt::Result {
+            unreachable!()
+        }
+
+        fn pop_annotation(&mut self) -> fmt::Result {
+            unreachable!()
+        }
+
+        fn push_annotation_at(&mut self, _: &(), cursor: Cursor) -> fmt::Result {
+            self.0.push(format!("push {:?}", cursor));
+            Ok(())
+        }
+
+        fn pop_annotation_at(&mut self, cursor: Cursor) -> fmt::Result {
+            self.0.push(format!("pop {:?}", cursor));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn cursor_callbacks() {
+        let doc: RcDoc<()> = RcDoc::text("ab")
+            .append(
+                RcDoc::hardline()
+                    .append(RcDoc::text("c").annotate(()))
+                    .append("d")
+                    .nest(2),
+            )
+            .annotate(());
+
+        let mut log = CursorLog(Vec::new());
+        doc.render_raw(80, &mut log).unwrap();
+        let cursor = |column, nesting, line_start| Cursor {
+            column,
+            nesting,
+            line_start,
+        };
+        let expected = [
+            format!("push {:?}", cursor(0, 0, true)),
+            format!("\"ab\" {:?}", cursor(0, 0, true)),
+            format!("newline {:?}", cursor(0, 2, true)),
+            format!("push {:?}", cursor(2, 2, true)),
+            format!("\"c\" {:?}", cursor(2, 2, true)),
+            format!("pop {:?}", cursor(3, 2, false)),
+            format!("\"d\" {:?}", cursor(3, 2, false)),
+            format!("pop {:?}", cursor(4, 2, false)),
+        ];
+        assert_eq!(log.0, expected);
+
+        let mut stream = StreamRender::new(RenderOptions::new(80), CursorLog(Vec::new()));
+        stream.push(&*doc).unwrap();
+        assert_eq!(stream.finish().unwrap().0, expected);
+    }
+
+    #[test]
+    fn layout_renders_like_best() {
+        let doc: RcDoc<()> = RcDoc::text("let x =")
+            .append(
+                RcDoc::line()
+                    .append(RcDoc::text("aaaa").annotate(()))
+                    .append(RcDoc::line())
+                    .append(RcDoc::text("bbbb").union(RcDoc::text("b").append(RcDoc::hardline())))
+                    .nest(4),
+            )
+            .append(RcDoc::hardline())
+            .append(RcDoc::text("c").nest(2))
+            .group()
+            .annotate(());
+
+        for width in [0, 6, 12, 80] {
+            for options in [
+                RenderOptions::new(width),
+                RenderOptions::new(width).indentation(Indentation::Tabs(4)),
+                RenderOptions::new(width).optimal(DefaultCost::new()),
+            ] {
+                let layout = doc.layout_with(&options).unwrap();
+
+                let mut expected = String::new();
+                doc.render_raw_with(&options, &mut TestWriter::new(FmtWrite::new(&mut expected)))
+                    .unwrap();
+                let mut actual = String::new();
+                layout
+                    .render(&mut TestWriter::new(FmtWrite::new(&mut actual)))
+                    .unwrap();
+                assert_eq!(actual, expected);
+
+                assert_eq!(layout.to_string(), doc.pretty_with(options).to_string());
+            }
+        }
+    }
+
+    #[test]
+    fn tab_indentation() {
+        let doc: RcDoc<()> = RcDoc::text("a")
+            .append(
+                RcDoc::hardline()
+                    .append("b")
+                    .append(RcDoc::hardline().append("c").nest(6))
+                    .nest(4),
+            )
+            .append(RcDoc::hardline())
+            .append("d");
+
+        let render = |indentation| {
+            let mut s = String::new();
+            doc.render_fmt_with(&RenderOptions::new(80).indentation(indentation), &mut s)
+                .unwrap();
+            s
+        };
+        assert_eq!(render(Indentation::Spaces), "a\n    b\n          c\nd");
+        assert_eq!(render(Indentation::Tabs(4)), "a\n\tb\n\t\t  c\nd");
+        assert_eq!(render(Indentation::Tabs(8)), "a\n    b\n\t  c\nd");
+    }
+
+    #[test]
+    fn smart_tab_indentation() {
+        let arena = Arena::<()>::new();
+        let args = arena
+            .intersperse(["x,", "y,", "z"].iter().cloned(), arena.hardline())
+            .align();
+        let doc = arena.text("{").append(
+            arena
+                .hardline()
+                .append("f(")
+                .append(args)
+                .append(")")
+                .append(arena.hardline().append("g()").nest(4))
+                .nest(4),
+        );
+
+        let mut s = String::new();
+        doc.render_fmt_with(
+            &RenderOptions::new(80).indentation(Indentation::SmartTabs(4)),
+            &mut s,
+        )
+        .unwrap();
+        assert_eq!(s, "{\n\tf(x,\n\t  y,\n\t  z)\n\t\tg()");
+    }
+
     #[test]
     fn block() {
         let doc: RcDoc<()> = RcDoc::group(
@@ -2070,6 +3659,40 @@ mod tests {
         test!(usize::max_value(), doc, "test test");
     }
 
+    #[test]
+    fn ribbon_width() {
+        let words = |words: &[&'static str]| {
+            RcDoc::intersperse(words.iter().cloned(), RcDoc::line()).group()
+        };
+        let doc: RcDoc<()> = RcDoc::text("xy")
+            .append(words(&["aaaa", "bbbb", "cccc"]))
+            .append(
+                RcDoc::hardline()
+                    .append(words(&["aaaa", "bbbb", "cccc", "dddd", "eeee"]))
+                    .nest(30),
+            );
+
+        let mut s = String::new();
+        doc.render_fmt_with(&RenderOptions::new(60).ribbon_width(16), &mut s)
+            .unwrap();
+        let indent = " ".repeat(30);
+        difference::assert_diff!(
+            &s,
+            &format!(
+                "xyaaaa bbbb cccc\n{0}aaaa\n{0}bbbb\n{0}cccc\n{0}dddd\n{0}eeee",
+                indent
+            ),
+            "\n",
+            0
+        );
+
+        test!(
+            60,
+            doc,
+            &format!("xyaaaa bbbb cccc\n{}aaaa bbbb cccc dddd eeee", indent)
+        );
+    }
+
     #[test]
     fn fail() {
         let fail_break: BoxDoc<()> = BoxDoc::fail().flat_alt(Doc::nil());
@@ -2080,6 +3703,75 @@ mod tests {
         test!(4, doc, "abc");
     }
 
+    #[test]
+    fn fail_with() {
+        let arena = Arena::<&str>::new();
+        let doc = arena
+            .text("a")
+            .append(arena.text("b").annotate("b"))
+            .append(
+                arena
+                    .hardline()
+                    .append(arena.fail_with("c failed").annotate("c"))
+                    .group()
+                    .annotate("inner"),
+            )
+            .append("d")
+            .annotate("outer");
+
+        let error = doc.1.render_string(80).unwrap_err();
+        assert_eq!(error.message(), Some("c failed"));
+        assert_eq!(error.annotations(), ["outer", "inner", "c"]);
+
+        let mut stream = StreamRender::new(RenderOptions::new(80), render::StringWrite::new());
+        let error = stream.push(&doc).unwrap_err();
+        assert_eq!(error.message(), Some("c failed"));
+        assert_eq!(error.annotations(), ["outer", "inner", "c"]);
+
+        let error = doc.layout(80).err().unwrap();
+        assert_eq!(error.message(), Some("c failed"));
+        assert_eq!(error.annotations(), ["outer", "inner", "c"]);
+
+        let options = RenderOptions::new(80).optimal(DefaultCost::new());
+        let error = doc.1.render_string_with(&options).unwrap_err();
+        assert_eq!(error.message(), Some("c failed"));
+
+        for options in [
+            RenderOptions::new(80),
+            RenderOptions::new(80).optimal(DefaultCost::new()),
+        ] {
+            // The failure is written out instead of leaving out part of the document silently
+            let options = options.lenient(true);
+            assert_eq!(doc.1.render_string_with(&options).unwrap(), "ab\nc failedd");
+
+            let mut stream = StreamRender::new(options.clone(), render::StringWrite::new());
+            stream.push(&doc).unwrap();
+            assert_eq!(stream.finish().unwrap().into_string(), "ab\nc failedd");
+        }
+    }
+
+    #[test]
+    fn lenient_falls_back_to_the_right_side_of_the_union() {
+        let arena = Arena::<()>::new();
+        let doc = arena
+            .text("a")
+            .append(arena.fail())
+            .union(arena.text("b").append(arena.fail()))
+            .append("c");
+        for options in [
+            RenderOptions::new(80),
+            RenderOptions::new(80).optimal(DefaultCost::new()),
+        ] {
+            assert!(doc.1.render_string_with(&options).is_err());
+
+            let options = options.lenient(true);
+            assert_eq!(doc.1.render_string_with(&options).unwrap(), "b<failed>c");
+
+            let options = options.fail_marker("?").elision_marker("...");
+            assert_eq!(doc.1.render_string_with(&options).unwrap(), "b?c");
+        }
+    }
+
     pub struct TestWriter<W> {
         upstream: W,
     }
@@ -0,0 +1,275 @@
+//! Document formatting of delimited lists such as `(a, b, c)`, `[a, b]` or `{ a; b }`. See
+//! `EncloseSep` for an example
+
+use crate::{DocAllocator, DocBuilder};
+
+/// How the elements of an `EncloseSep` list are laid out when the list does not fit on a single
+/// line
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ListStyle {
+    /// Every element on its own line, nested inside the delimiters which are on lines of their
+    /// own
+    ///
+    /// ```text
+    /// (
+    ///     a,
+    ///     b
+    /// )
+    /// ```
+    Block,
+    /// Every element on its own line, aligned under the first element (as in Lisp)
+    ///
+    /// ```text
+    /// (a,
+    ///  b)
+    /// ```
+    Aligned,
+    /// Every element on its own line, starting with the separator (as in Haskell)
+    ///
+    /// ```text
+    /// ( a
+    /// , b
+    /// )
+    /// ```
+    Leading,
+}
+
+/// Formats a list of documents between the `open` and `close` delimiters, separated by
+/// `separator`. The list is laid out on a single line if it fits, and otherwise with one element
+/// per line as described by its `ListStyle`.
+///
+/// ```
+/// use pretty::{DocAllocator, ListStyle};
+///
+/// let arena = pretty::Arena::<()>::new();
+/// let list = |style| {
+///     arena
+///         .enclose_sep("[", "]", ",", ["first", "second", "third"])
+///         .style(style)
+///         .trailing(true)
+///         .format()
+/// };
+///
+/// assert_eq!(list(ListStyle::Block).1.pretty(80).to_string(), "[first, second, third]");
+/// assert_eq!(
+///     list(ListStyle::Block).1.pretty(10).to_string(),
+///     "[\n    first,\n    second,\n    third,\n]",
+/// );
+/// assert_eq!(
+///     list(ListStyle::Aligned).1.pretty(10).to_string(),
+///     "[first,\n second,\n third]",
+/// );
+/// assert_eq!(
+///     list(ListStyle::Leading).1.pretty(10).to_string(),
+///     "[ first\n, second\n, third\n]",
+/// );
+/// ```
+pub struct EncloseSep<'doc, D, A>
+where
+    D: DocAllocator<'doc, A>,
+{
+    open: DocBuilder<'doc, D, A>,
+    close: DocBuilder<'doc, D, A>,
+    separator: DocBuilder<'doc, D, A>,
+    docs: Vec<DocBuilder<'doc, D, A>>,
+    style: ListStyle,
+    nest: isize,
+    trailing: bool,
+    spaced: bool,
+    empty: Option<DocBuilder<'doc, D, A>>,
+}
+
+impl<'doc, D, A> EncloseSep<'doc, D, A>
+where
+    D: DocAllocator<'doc, A>,
+{
+    pub fn new(
+        open: DocBuilder<'doc, D, A>,
+        close: DocBuilder<'doc, D, A>,
+        separator: DocBuilder<'doc, D, A>,
+        docs: Vec<DocBuilder<'doc, D, A>>,
+    ) -> Self {
+        EncloseSep {
+            open,
+            close,
+            separator,
+            docs,
+            style: ListStyle::Block,
+            nest: 4,
+            trailing: false,
+            spaced: false,
+            empty: None,
+        }
+    }
+
+    /// Sets how the list is laid out when it does not fit on a single line. Defaults to
+    /// `ListStyle::Block`.
+    pub fn style(mut self, style: ListStyle) -> Self {
+        self.style = style;
+        self
+    }
+
+    /// Sets how far the elements are nested in `ListStyle::Block`. Defaults to 4.
+    pub fn nest(mut self, nest: isize) -> Self {
+        self.nest = nest;
+        self
+    }
+
+    /// Adds the separator after the last element as well when the list is laid out on multiple
+    /// lines in `ListStyle::Block`.
+    pub fn trailing(mut self, trailing: bool) -> Self {
+        self.trailing = trailing;
+        self
+    }
+
+    /// Puts spaces inside the delimiters when the list is laid out on a single line, as in
+    /// `{ a; b }`.
+    pub fn spaced(mut self, spaced: bool) -> Self {
+        self.spaced = spaced;
+        self
+    }
+
+    /// Sets the document used for a list without elements. Defaults to `open` followed by `close`.
+    pub fn empty(mut self, empty: DocBuilder<'doc, D, A>) -> Self {
+        self.empty = Some(empty);
+        self
+    }
+}
+
+impl<'doc, D, A> EncloseSep<'doc, D, A>
+where
+    D: DocAllocator<'doc, A>,
+    D::Doc: Clone,
+    A: Clone,
+{
+    pub fn format(self) -> DocBuilder<'doc, D, A> {
+        let arena = self.open.0;
+        let EncloseSep {
+            open,
+            close,
+            separator,
+            docs,
+            style,
+            nest,
+            trailing,
+            spaced,
+            empty,
+        } = self;
+
+        if docs.is_empty() {
+            return empty.unwrap_or_else(|| open.append(close));
+        }
+
+        let inner_line = if spaced { arena.line() } else { arena.line_() };
+        match style {
+            ListStyle::Block => {
+                let trailing = if trailing {
+                    separator.clone().flat_alt(arena.nil())
+                } else {
+                    arena.nil()
+                };
+                let docs = arena.intersperse(docs, separator.append(arena.line()));
+                open.append(inner_line.clone().append(docs).append(trailing).nest(nest))
+                    .append(inner_line)
+                    .append(close)
+                    .group()
+            }
+            ListStyle::Aligned => {
+                let space = if spaced { arena.space() } else { arena.nil() };
+                let docs = arena.intersperse(docs, separator.append(arena.line()));
+                open.append(space.clone())
+                    .append(docs.align())
+                    .append(space)
+                    .append(close)
+                    .group()
+            }
+            ListStyle::Leading => {
+                // Lines up the first element with the ones after the separators
+                let space = if spaced {
+                    arena.space()
+                } else {
+                    arena.space().flat_alt(arena.nil())
+                };
+                let separator = arena.line_().append(separator).append(" ");
+                open.append(space)
+                    .append(arena.intersperse(docs, separator))
+                    .append(inner_line)
+                    .append(close)
+                    .align()
+                    .group()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::Arena;
+
+    #[test]
+    fn enclose_sep() {
+        let arena = &Arena::<()>::new();
+        let list = |docs: &[&'static str], style| {
+            arena
+                .enclose_sep("{", "}", ";", docs.iter().copied())
+                .style(style)
+                .spaced(true)
+        };
+
+        for style in [ListStyle::Block, ListStyle::Aligned, ListStyle::Leading] {
+            assert_eq!(list(&[], style).format().1.pretty(80).to_string(), "{}");
+            assert_eq!(
+                list(&[], style)
+                    .empty(arena.text("{ }"))
+                    .format()
+                    .1
+                    .pretty(80)
+                    .to_string(),
+                "{ }"
+            );
+            assert_eq!(
+                list(&["a", "b"], style).format().1.pretty(80).to_string(),
+                "{ a; b }"
+            );
+        }
+
+        assert_eq!(
+            list(&["a", "b"], ListStyle::Block)
+                .nest(2)
+                .trailing(true)
+                .format()
+                .1
+                .pretty(4)
+                .to_string(),
+            "{\n  a;\n  b;\n}"
+        );
+        assert_eq!(
+            list(&["a", "b"], ListStyle::Aligned)
+                .format()
+                .1
+                .pretty(4)
+                .to_string(),
+            "{ a;\n  b }"
+        );
+        assert_eq!(
+            list(&["a", "b"], ListStyle::Leading)
+                .format()
+                .1
+                .pretty(4)
+                .to_string(),
+            "{ a\n; b\n}"
+        );
+
+        // A single element which does not fit is still broken
+        assert_eq!(
+            list(&["element"], ListStyle::Block)
+                .format()
+                .1
+                .pretty(4)
+                .to_string(),
+            "{\n    element\n}"
+        );
+    }
+}
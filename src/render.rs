@@ -1,10 +1,32 @@
-use std::{cmp, fmt, io};
+use std::{borrow::Cow, cmp, collections::HashMap, fmt, io, mem, sync::Arc};
 
 #[cfg(feature = "termcolor")]
 use termcolor::{ColorSpec, WriteColor};
 
 use crate::{Doc, DocPtr};
 
+mod error;
+mod layout;
+mod optimal;
+mod overflow;
+mod spans;
+mod stream;
+mod trace;
+mod width;
+mod writer;
+
+pub use self::error::RenderError;
+pub(crate) use self::error::StringWrite;
+use self::layout::BufferWrite;
+pub use self::layout::{Layout, LayoutToken, Tokens};
+pub use self::optimal::{CostFunction, DefaultCost};
+pub use self::overflow::{Overflow, OverflowWriter};
+pub use self::spans::{Position, Span, SpanWriter};
+pub use self::stream::StreamRender;
+pub use self::trace::{LayoutStats, LayoutTracer};
+pub use self::width::{UnicodeWidth, WidthMeasure};
+use self::writer::{WriteState, Writer};
+
 /// Trait representing the operations necessary to render a document
 pub trait Render {
     type Error;
@@ -19,7 +41,200 @@ pub trait Render {
         Ok(())
     }
 
+    /// Writes a piece of the document's text which starts at `cursor`. Indentation and line
+    /// breaks are written with `write_str_all` instead.
+    ///
+    /// The default implementation ignores `cursor` and calls `write_str_all`.
+    fn write_text(&mut self, s: &str, cursor: Cursor) -> Result<(), Self::Error> {
+        let _ = cursor;
+        self.write_str_all(s)
+    }
+
+    /// Called after a line break has been written, before the indentation of the new line.
+    fn newline(&mut self, cursor: Cursor) -> Result<(), Self::Error> {
+        let _ = cursor;
+        Ok(())
+    }
+
     fn fail_doc(&self) -> Self::Error;
+
+    /// Returns the output as a `Rewind` if it can be rolled back, or `None` (the default).
+    ///
+    /// The greedy layout algorithm writes the left side of a `union` directly to an output which
+    /// can be rolled back, instead of buffering it until it is known to fit.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let doc = arena.text("too long").union(arena.text("short"));
+    ///
+    /// let mut s = String::new();
+    /// doc.render_raw(6, &mut s).unwrap();
+    /// assert_eq!(s, "short");
+    /// ```
+    fn rewind(&mut self) -> Option<&mut dyn Rewind<Error = Self::Error>> {
+        None
+    }
+}
+
+/// An output which can discard what has been written to it (see `Render::rewind`)
+pub trait Rewind: Render {
+    /// Returns a checkpoint which `rollback` can return the output to.
+    fn checkpoint(&mut self) -> usize;
+
+    /// Discards everything which has been written, including annotations which have been pushed
+    /// or popped, since `checkpoint` was returned by `checkpoint`.
+    ///
+    /// Checkpoints are rolled back to in the reverse order of their creation, and checkpoints
+    /// created after the one which is rolled back to are not used again.
+    fn rollback(&mut self, checkpoint: usize) -> Result<(), Self::Error>;
+}
+
+impl Render for String {
+    type Error = fmt::Error;
+
+    fn write_str(&mut self, s: &str) -> Result<usize, fmt::Error> {
+        self.push_str(s);
+        Ok(s.len())
+    }
+
+    fn fail_doc(&self) -> Self::Error {
+        fmt::Error
+    }
+
+    fn rewind(&mut self) -> Option<&mut dyn Rewind<Error = fmt::Error>> {
+        Some(self)
+    }
+}
+
+impl Rewind for String {
+    fn checkpoint(&mut self) -> usize {
+        self.len()
+    }
+
+    fn rollback(&mut self, checkpoint: usize) -> fmt::Result {
+        self.truncate(checkpoint);
+        Ok(())
+    }
+}
+
+impl<A> RenderAnnotated<'_, A> for String {
+    fn push_annotation(&mut self, _: &A) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn pop_annotation(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl Render for Vec<u8> {
+    type Error = io::Error;
+
+    fn write_str(&mut self, s: &str) -> io::Result<usize> {
+        self.extend_from_slice(s.as_bytes());
+        Ok(s.len())
+    }
+
+    fn fail_doc(&self) -> Self::Error {
+        io::Error::other("Document failed to render")
+    }
+
+    fn rewind(&mut self) -> Option<&mut dyn Rewind<Error = io::Error>> {
+        Some(self)
+    }
+}
+
+impl Rewind for Vec<u8> {
+    fn checkpoint(&mut self) -> usize {
+        self.len()
+    }
+
+    fn rollback(&mut self, checkpoint: usize) -> io::Result<()> {
+        self.truncate(checkpoint);
+        Ok(())
+    }
+}
+
+impl<A> RenderAnnotated<'_, A> for Vec<u8> {
+    fn push_annotation(&mut self, _: &A) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn pop_annotation(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn fail_doc_with(&self, error: RenderError<&A>) -> Self::Error {
+        io::Error::other(error.to_string())
+    }
+}
+
+/// Where the renderer is in the output when it calls one of the position aware methods of
+/// `Render` and `RenderAnnotated`.
+///
+/// ```
+/// use pretty::{Cursor, FmtWrite, Render, RenderAnnotated, RcDoc};
+///
+/// // Prefixes every line with its line number
+/// struct LineNumbers<W> {
+///     upstream: W,
+///     line: usize,
+/// }
+///
+/// impl<W: Render> Render for LineNumbers<W> {
+///     type Error = W::Error;
+///
+///     fn write_str(&mut self, s: &str) -> Result<usize, W::Error> {
+///         self.upstream.write_str(s)
+///     }
+///
+///     fn write_text(&mut self, s: &str, cursor: Cursor) -> Result<(), W::Error> {
+///         if self.line == 0 {
+///             self.newline(cursor)?;
+///         }
+///         self.upstream.write_str_all(s)
+///     }
+///
+///     fn newline(&mut self, _: Cursor) -> Result<(), W::Error> {
+///         self.line += 1;
+///         self.upstream.write_str_all(&format!("{} | ", self.line))
+///     }
+///
+///     fn fail_doc(&self) -> W::Error {
+///         self.upstream.fail_doc()
+///     }
+/// }
+///
+/// impl<W: Render> RenderAnnotated<'_, ()> for LineNumbers<W> {
+///     fn push_annotation(&mut self, _: &()) -> Result<(), W::Error> {
+///         Ok(())
+///     }
+///
+///     fn pop_annotation(&mut self) -> Result<(), W::Error> {
+///         Ok(())
+///     }
+/// }
+///
+/// let doc = RcDoc::<()>::text("{")
+///     .append(RcDoc::hardline().append("x").nest(4))
+///     .append(RcDoc::hardline())
+///     .append("}");
+/// let mut s = String::new();
+/// let mut out = LineNumbers { upstream: FmtWrite::new(&mut s), line: 0 };
+/// doc.render_raw(80, &mut out).unwrap();
+/// assert_eq!(s, "1 | {\n2 |     x\n3 | }");
+/// ```
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Cursor {
+    /// The current column.
+    pub column: usize,
+    /// The indentation of the current line, in columns.
+    pub nesting: usize,
+    /// `true` if no text has been written on the current line yet, either because a line break
+    /// was just written or because nothing has been written at all.
+    pub line_start: bool,
 }
 
 /// Writes to something implementing `std::io::Write`
@@ -48,7 +263,7 @@ where
     }
 
     fn fail_doc(&self) -> Self::Error {
-        io::Error::new(io::ErrorKind::Other, "Document failed to render")
+        io::Error::other("Document failed to render")
     }
 }
 
@@ -86,6 +301,27 @@ where
 pub trait RenderAnnotated<'a, A>: Render {
     fn push_annotation(&mut self, annotation: &'a A) -> Result<(), Self::Error>;
     fn pop_annotation(&mut self) -> Result<(), Self::Error>;
+
+    /// Pushes an annotation which starts at `cursor`. The default implementation calls
+    /// `push_annotation`.
+    fn push_annotation_at(&mut self, annotation: &'a A, cursor: Cursor) -> Result<(), Self::Error> {
+        let _ = cursor;
+        self.push_annotation(annotation)
+    }
+
+    /// Pops the innermost annotation, which ends at `cursor`. The default implementation calls
+    /// `pop_annotation`.
+    fn pop_annotation_at(&mut self, cursor: Cursor) -> Result<(), Self::Error> {
+        let _ = cursor;
+        self.pop_annotation()
+    }
+
+    /// Creates the error returned when a `fail` document is rendered, from a description of
+    /// where it failed. The default implementation calls `fail_doc`.
+    fn fail_doc_with(&self, error: RenderError<&'a A>) -> Self::Error {
+        let _ = error;
+        self.fail_doc()
+    }
 }
 
 impl<A, W> RenderAnnotated<'_, A> for IoWrite<W>
@@ -99,6 +335,10 @@ where
     fn pop_annotation(&mut self) -> Result<(), Self::Error> {
         Ok(())
     }
+
+    fn fail_doc_with(&self, error: RenderError<&A>) -> Self::Error {
+        io::Error::other(error.to_string())
+    }
 }
 
 impl<A, W> RenderAnnotated<'_, A> for FmtWrite<W>
@@ -146,7 +386,7 @@ where
     }
 
     fn fail_doc(&self) -> Self::Error {
-        io::Error::new(io::ErrorKind::Other, "Document failed to render")
+        io::Error::other("Document failed to render")
     }
 }
 
@@ -167,78 +407,49 @@ where
             None => self.upstream.reset(),
         }
     }
-}
 
-enum Annotation<'a, A> {
-    Push(&'a A),
-    Pop,
+    fn fail_doc_with(&self, error: RenderError<&ColorSpec>) -> Self::Error {
+        io::Error::other(error.to_string())
+    }
 }
 
-struct BufferWrite<'a, A> {
-    buffer: String,
-    annotations: Vec<(usize, Annotation<'a, A>)>,
-}
+/// Receives the layout chosen by one of the layout algorithms
+trait Output {
+    type Error;
 
-impl<'a, A> BufferWrite<'a, A> {
-    fn new() -> Self {
-        BufferWrite {
-            buffer: String::new(),
-            annotations: Vec::new(),
-        }
-    }
+    /// Writes a (non-empty) piece of text which is `len` columns wide
+    fn write_text(&mut self, s: &str, len: usize) -> Result<(), Self::Error>;
 
-    fn render<W>(&mut self, render: &mut W) -> Result<(), W::Error>
-    where
-        W: RenderAnnotated<'a, A>,
-        W: ?Sized,
-    {
-        let mut start = 0;
-        for (end, annotation) in &self.annotations {
-            let s = &self.buffer[start..*end];
-            if !s.is_empty() {
-                render.write_str_all(s)?;
-            }
-            start = *end;
-            match annotation {
-                Annotation::Push(a) => render.push_annotation(a)?,
-                Annotation::Pop => render.pop_annotation()?,
-            }
-        }
-        let s = &self.buffer[start..];
-        if !s.is_empty() {
-            render.write_str_all(s)?;
-        }
-        Ok(())
-    }
-}
+    /// Starts a new line which is indented by `indent`
+    fn write_newline(&mut self, indent: Indent) -> Result<(), Self::Error>;
 
-impl<A> Render for BufferWrite<'_, A> {
-    type Error = ();
+    /// Creates the error for a `fail` document with the given message
+    fn fail_doc(&self, message: Option<&str>) -> Self::Error;
 
-    fn write_str(&mut self, s: &str) -> Result<usize, Self::Error> {
-        self.buffer.push_str(s);
-        Ok(s.len())
+    /// Returns `true` once the output does not accept anything more, so that laying out the rest
+    /// of the document can be skipped
+    fn is_done(&self) -> bool {
+        false
     }
 
-    fn write_str_all(&mut self, s: &str) -> Result<(), Self::Error> {
-        self.buffer.push_str(s);
-        Ok(())
-    }
+    /// Returns a checkpoint to roll back to, or `None` if the output can not be rolled back (see
+    /// `Render::rewind`)
+    fn checkpoint(&mut self) -> Option<usize>;
 
-    fn fail_doc(&self) -> Self::Error {}
-}
+    /// Rolls back to a checkpoint returned by `checkpoint`
+    fn rollback(&mut self, checkpoint: usize) -> Result<(), Self::Error>;
 
-impl<'a, A> RenderAnnotated<'a, A> for BufferWrite<'a, A> {
-    fn push_annotation(&mut self, a: &'a A) -> Result<(), Self::Error> {
-        self.annotations
-            .push((self.buffer.len(), Annotation::Push(a)));
-        Ok(())
+    /// Called once `checkpoint`, and every checkpoint created after it, will not be rolled back
+    /// to
+    fn commit(&mut self, checkpoint: usize) {
+        let _ = checkpoint;
     }
+}
 
-    fn pop_annotation(&mut self) -> Result<(), Self::Error> {
-        self.annotations.push((self.buffer.len(), Annotation::Pop));
-        Ok(())
-    }
+trait OutputAnnotated<'d, A>: Output {
+    fn push_annotation(&mut self, annotation: &'d A) -> Result<(), Self::Error>;
+
+    fn pop_annotation(&mut self) -> Result<(), Self::Error>;
 }
 
 macro_rules! make_spaces {
@@ -282,40 +493,508 @@ where
     }
 }
 
-pub fn best<'a, W, T, A>(doc: &Doc<'a, T, A>, width: usize, out: &mut W) -> Result<(), W::Error>
+/// Options controlling how a document is laid out.
+///
+/// ```
+/// use pretty::{Arena, DocAllocator, RenderOptions};
+///
+/// let arena = Arena::<()>::new();
+/// let words = arena.intersperse(["aaa", "bbb", "ccc"].iter().cloned(), arena.line());
+/// let doc = arena.text("{").append(arena.hardline().append(words.group()).nest(8));
+///
+/// assert_eq!(doc.1.pretty(80).to_string(), "{\n        aaa bbb ccc");
+/// assert_eq!(
+///     doc.1.pretty_with(RenderOptions::new(80).ribbon_width(8)).to_string(),
+///     "{\n        aaa\n        bbb\n        ccc",
+/// );
+/// ```
+#[derive(Clone)]
+pub struct RenderOptions {
+    width: usize,
+    ribbon_width: Option<usize>,
+    indentation: Indentation,
+    newline: Cow<'static, str>,
+    algorithm: Algorithm,
+    // `None` for the default `UnicodeWidth`, whose widths are stored in `MeasuredText` documents
+    width_measure: Option<Arc<dyn WidthMeasure + Send + Sync>>,
+    max_lines: Option<usize>,
+    max_bytes: Option<usize>,
+    elision_marker: Cow<'static, str>,
+    lenient: bool,
+    fail_marker: Cow<'static, str>,
+}
+
+#[derive(Clone)]
+enum Algorithm {
+    Greedy,
+    Optimal(Arc<dyn CostFunction + Send + Sync>),
+}
+
+impl fmt::Debug for RenderOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RenderOptions")
+            .field("width", &self.width)
+            .field("ribbon_width", &self.ribbon_width)
+            .field("indentation", &self.indentation)
+            .field("newline", &self.newline)
+            .field("max_lines", &self.max_lines)
+            .field("max_bytes", &self.max_bytes)
+            .field("elision_marker", &self.elision_marker)
+            .field("lenient", &self.lenient)
+            .field("fail_marker", &self.fail_marker)
+            .field(
+                "algorithm",
+                &match self.algorithm {
+                    Algorithm::Greedy => "Greedy",
+                    Algorithm::Optimal(_) => "Optimal",
+                },
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+/// How the indentation at the start of each line is written.
+///
+/// Indentation is always measured in columns, so `nest(4)` adds four columns of indentation
+/// regardless of which variant is used. The tab width decides how many columns a tab occupies.
+///
+/// ```
+/// use pretty::{Arena, DocAllocator, Indentation, RenderOptions};
+///
+/// let arena = Arena::<()>::new();
+/// let doc = arena.text("func main() {")
+///     .append(
+///         arena.hardline()
+///             .append("call(")
+///             .append(arena.text("a,").append(arena.hardline()).append("b)").align())
+///             .nest(4),
+///     )
+///     .append(arena.hardline())
+///     .append("}");
+///
+/// let options = RenderOptions::new(80).indentation(Indentation::SmartTabs(4));
+/// assert_eq!(
+///     doc.1.pretty_with(options).to_string(),
+///     "func main() {\n\tcall(a,\n\t     b)\n}",
+/// );
+/// ```
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Indentation {
+    /// Indent using only spaces
+    #[default]
+    Spaces,
+    /// Indent using as many tabs of the given width as possible, followed by spaces for any
+    /// remaining columns
+    Tabs(usize),
+    /// Like `Tabs` for block indentation (introduced by `nest`) but uses spaces for any
+    /// indentation introduced by `align` (and the combinators built on it, such as `hang`), so
+    /// that aligned text lines up regardless of the tab width used by the reader
+    SmartTabs(usize),
+}
+
+impl RenderOptions {
+    /// Creates options which lay out the document within `width` columns.
+    pub fn new(width: usize) -> Self {
+        RenderOptions {
+            width,
+            ribbon_width: None,
+            indentation: Indentation::Spaces,
+            newline: Cow::Borrowed("\n"),
+            algorithm: Algorithm::Greedy,
+            width_measure: None,
+            max_lines: None,
+            max_bytes: None,
+            elision_marker: Cow::Borrowed("…"),
+            lenient: false,
+            fail_marker: Cow::Borrowed("<failed>"),
+        }
+    }
+
+    /// Limits the number of non-indentation characters on each line (the "ribbon").
+    ///
+    /// A line fits only if it ends within `width` columns and contains at most `ribbon_width`
+    /// columns after its indentation.
+    pub fn ribbon_width(mut self, ribbon_width: usize) -> Self {
+        self.ribbon_width = Some(ribbon_width);
+        self
+    }
+
+    /// Sets the ribbon width as a fraction of the page width, as done by Leijen's `renderPretty`.
+    ///
+    /// `fraction` is clamped to the range `0.0..=1.0`.
+    pub fn ribbon_fraction(self, fraction: f64) -> Self {
+        let fraction = fraction.clamp(0.0, 1.0);
+        let ribbon_width = (self.width as f64 * fraction).round() as usize;
+        self.ribbon_width(ribbon_width)
+    }
+
+    /// Sets how indentation is written. Defaults to `Indentation::Spaces`.
+    pub fn indentation(mut self, indentation: Indentation) -> Self {
+        self.indentation = indentation;
+        self
+    }
+
+    /// Sets the sequence written for each line break. Defaults to `"\n"`.
+    ///
+    /// ```
+    /// use pretty::{BoxDoc, RenderOptions};
+    ///
+    /// let doc = BoxDoc::<()>::text("a").append(BoxDoc::hardline()).append("b");
+    /// let options = RenderOptions::new(80).newline("\r\n");
+    /// assert_eq!(doc.pretty_with(options).to_string(), "a\r\nb");
+    /// ```
+    pub fn newline(mut self, newline: impl Into<Cow<'static, str>>) -> Self {
+        self.newline = newline.into();
+        self
+    }
+
+    /// Lays out the document with the layout which is cheapest according to `cost` instead of
+    /// the default greedy algorithm, which decides each group using only the rest of the current
+    /// line.
+    ///
+    /// The optimal layout considers every choice between laying out groups flat or broken and
+    /// both sides of every `union`, so it is slower than the greedy algorithm. `ribbon_width` is
+    /// not used by the optimal layout, any such preference should be expressed through `cost`
+    /// instead.
+    pub fn optimal(mut self, cost: impl CostFunction + Send + Sync + 'static) -> Self {
+        self.algorithm = Algorithm::Optimal(Arc::new(cost));
+        self
+    }
+
+    /// Lays out the document with the greedy algorithm (the default)
+    pub fn greedy(mut self) -> Self {
+        self.algorithm = Algorithm::Greedy;
+        self
+    }
+
+    /// Sets how the width of text is measured (defaults to `UnicodeWidth::new()`).
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator, RenderOptions, UnicodeWidth};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let doc = arena.text("±±").append(arena.line()).append("±±").group();
+    ///
+    /// assert_eq!(doc.1.pretty(5).to_string(), "±± ±±");
+    /// let cjk = RenderOptions::new(5).width_measure(UnicodeWidth::new().ambiguous_wide(true));
+    /// assert_eq!(doc.1.pretty_with(cjk).to_string(), "±±\n±±");
+    /// ```
+    pub fn width_measure(
+        mut self,
+        width_measure: impl WidthMeasure + Send + Sync + 'static,
+    ) -> Self {
+        self.width_measure = Some(Arc::new(width_measure));
+        self
+    }
+
+    /// Stops rendering after `max_lines` lines (at least 1) and writes the elision marker on a
+    /// line of its own instead of the rest of the document. The marker is left out if the rest
+    /// of the document is only empty lines.
+    ///
+    /// The greedy and streaming renderers stop laying out the document as soon as text is cut
+    /// off, so this is cheap even for huge documents.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator, RenderOptions};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let doc = arena.intersperse((0..100_000).map(|i| arena.as_string(i)), arena.hardline());
+    ///
+    /// let options = RenderOptions::new(80).max_lines(3).elision_marker("[more lines]");
+    /// assert_eq!(doc.1.pretty_with(options).to_string(), "0\n1\n2\n[more lines]");
+    /// ```
+    pub fn max_lines(mut self, max_lines: usize) -> Self {
+        self.max_lines = Some(cmp::max(max_lines, 1));
+        self
+    }
+
+    /// Stops rendering after `max_bytes` bytes and writes the elision marker instead of the rest
+    /// of the document. Text is only cut at character boundaries.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator, RenderOptions};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let doc = arena.text("hello").append(arena.line()).append("world").group();
+    ///
+    /// let options = RenderOptions::new(80).max_bytes(8);
+    /// assert_eq!(doc.1.pretty_with(options).to_string(), "hello wo…");
+    /// ```
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Sets the marker that is written when the output is cut short by `max_lines` or
+    /// `max_bytes` (defaults to `…`).
+    pub fn elision_marker(mut self, elision_marker: impl Into<Cow<'static, str>>) -> Self {
+        self.elision_marker = elision_marker.into();
+        self
+    }
+
+    /// Writes `fail` documents which are not inside the left side of a `union` to the output,
+    /// instead of making rendering fail. A `fail_with` document is written as its message and a
+    /// `fail` document as the fail marker (see `fail_marker`).
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator, RenderOptions};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let doc = arena.text("a").append(arena.fail_with("<unsupported>")).append("b");
+    ///
+    /// let error = doc.1.render_string(80).unwrap_err();
+    /// assert_eq!(error.message(), Some("<unsupported>"));
+    /// let options = RenderOptions::new(80).lenient(true);
+    /// assert_eq!(doc.1.render_string_with(&options).unwrap(), "a<unsupported>b");
+    /// ```
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// The text written in place of `fail` documents when rendering leniently (defaults to
+    /// `<failed>`).
+    pub fn fail_marker(mut self, fail_marker: impl Into<Cow<'static, str>>) -> Self {
+        self.fail_marker = fail_marker.into();
+        self
+    }
+
+    /// The text written in place of a failing document when rendering leniently
+    pub(crate) fn fail_text<'s>(&'s self, message: Option<&'s str>) -> &'s str {
+        message.unwrap_or(&self.fail_marker)
+    }
+
+    /// Copies the options with a different page width
+    pub(crate) fn with_page_width(&self, width: usize) -> Self {
+        RenderOptions {
+            width,
+            ..self.clone()
+        }
+    }
+
+    /// The width of `text` when written at `column`
+    fn measure(&self, text: &str, column: usize) -> usize {
+        match self.width_measure {
+            Some(ref width_measure) => width_measure.width(text, column),
+            None => UnicodeWidth::new().width(text, column),
+        }
+    }
+
+    /// The width of the text of a text document when written at `column`. With the default
+    /// measure this is the length of the text, as the width of text which is not just ascii is
+    /// stored in a `MeasuredText` document.
+    fn text_width(&self, text: &str, column: usize) -> usize {
+        match self.width_measure {
+            Some(ref width_measure) => width_measure.width(text, column),
+            None => text.len(),
+        }
+    }
+
+    /// The width of a `MeasuredText` document whose stored width is `len`, if it does not need
+    /// to be measured again with a custom `width_measure`
+    fn measured_len(&self, len: usize) -> Option<usize> {
+        match self.width_measure {
+            Some(_) => None,
+            None => Some(len),
+        }
+    }
+
+    /// The width of the `text` of a `MeasuredText` document whose stored width is `len`, when
+    /// written at `column`
+    fn measured_text_width(&self, len: usize, text: &str, column: usize) -> usize {
+        self.measured_len(len)
+            .unwrap_or_else(|| self.text_width(text, column))
+    }
+
+    /// The maximum width of a line
+    fn line_width(&self, line_indent: usize) -> usize {
+        match self.ribbon_width {
+            Some(ribbon_width) => cmp::min(self.width, line_indent.saturating_add(ribbon_width)),
+            None => self.width,
+        }
+    }
+}
+
+impl From<usize> for RenderOptions {
+    fn from(width: usize) -> Self {
+        RenderOptions::new(width)
+    }
+}
+
+pub fn best<'a, W, T, A>(
+    doc: &Doc<'a, T, A>,
+    options: &RenderOptions,
+    out: &mut W,
+) -> Result<(), W::Error>
 where
     T: DocPtr<'a, A> + 'a,
     for<'b> W: RenderAnnotated<'b, A>,
     W: ?Sized,
+{
+    best_traced(doc, options, out, &mut ())
+}
+
+/// Like `best`, but reports the work done by the greedy layout algorithm to `tracer`.
+pub fn best_traced<'a, W, T, A, Tr>(
+    doc: &Doc<'a, T, A>,
+    options: &RenderOptions,
+    out: &mut W,
+    tracer: &mut Tr,
+) -> Result<(), W::Error>
+where
+    T: DocPtr<'a, A> + 'a,
+    for<'b> W: RenderAnnotated<'b, A>,
+    W: ?Sized,
+    Tr: ?Sized + LayoutTracer,
+{
+    let temp_arena = &typed_arena::Arena::new();
+    let mut state = WriteState::new();
+    lay_out(
+        doc,
+        options,
+        temp_arena,
+        &mut Writer::new(options, out, &mut state),
+        tracer,
+    )
+}
+
+/// Lays out `doc` without writing it anywhere, so that the result can be rendered any number of
+/// times.
+pub fn layout<'a, T, A>(
+    doc: &Doc<'a, T, A>,
+    options: &RenderOptions,
+) -> Result<Layout<A>, RenderError<A>>
+where
+    T: DocPtr<'a, A> + 'a,
+    A: Clone,
 {
     let temp_arena = &typed_arena::Arena::new();
+    let mut layout = Layout::new(options.clone());
+    lay_out(doc, options, temp_arena, &mut layout, &mut ())?;
+    Ok(layout)
+}
+
+fn lay_out<'d, 'a, O, T, A, Tr>(
+    doc: &'d Doc<'a, T, A>,
+    options: &'d RenderOptions,
+    temp_arena: &'d typed_arena::Arena<T>,
+    out: &mut O,
+    tracer: &mut Tr,
+) -> Result<(), O::Error>
+where
+    T: DocPtr<'a, A> + 'a,
+    O: ?Sized + OutputAnnotated<'d, A>,
+    Tr: ?Sized + LayoutTracer,
+{
+    if let Algorithm::Optimal(cost) = &options.algorithm {
+        return optimal::best(doc, options, &**cost, temp_arena, out);
+    }
+
     Best {
         pos: 0,
-        bcmds: vec![(0, Mode::Break, doc)],
+        line_indent: Indent::default(),
+        bcmds: vec![(Indent::default(), Mode::Break, doc)],
         fcmds: vec![],
         annotation_levels: vec![],
-        width,
+        attempts: vec![],
+        buffer: None,
+        union_outcomes: HashMap::new(),
+        scanned: usize::MAX,
+        options,
         temp_arena,
+        tracer,
     }
-    .best(0, out)?;
-
-    Ok(())
+    .best(out)
 }
 
-#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 enum Mode {
     Break,
     Flat,
 }
 
-type Cmd<'d, 'a, T, A> = (usize, Mode, &'d Doc<'a, T, A>);
+/// The indentation of a document, in columns
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+struct Indent {
+    width: usize,
+    // The trailing part of `width` which was introduced by aligning to a column and should
+    // therefore always be written as spaces when using smart tabs
+    align: usize,
+}
+
+impl Indent {
+    fn nest(self, off: isize) -> Indent {
+        // Once https://doc.rust-lang.org/std/primitive.usize.html#method.saturating_add_signed is stable
+        // this can be replaced
+        let add = |n: usize| {
+            if off >= 0 {
+                n.saturating_add(off as usize)
+            } else {
+                n.saturating_sub(off.unsigned_abs())
+            }
+        };
+        let width = add(self.width);
+        // Nesting inside an aligned block continues the alignment
+        let align = if self.align == 0 {
+            0
+        } else {
+            cmp::min(add(self.align), width)
+        };
+        Indent { width, align }
+    }
+
+    fn align(self, column: usize) -> Indent {
+        let block = self.width - self.align;
+        Indent {
+            width: column,
+            align: column.saturating_sub(block),
+        }
+    }
+}
+
+type Cmd<'d, 'a, T, A> = (Indent, Mode, &'d Doc<'a, T, A>);
+
+// Whether the left side of a union can be used depends on the left side, the position, the
+// indentation of the current line and the indentation and mode of the union
+type UnionKey<'a, T, A> = (*const Doc<'a, T, A>, usize, Indent, Indent, Mode);
 
-fn write_newline<W>(ind: usize, out: &mut W) -> Result<(), W::Error>
+// The commands after a union which were looked at while trying its left side, and whether the left
+// side was used
+type UnionOutcome<'a, T, A> = (Vec<(Indent, Mode, *const Doc<'a, T, A>)>, bool);
+
+/// Returns the text of the document inside a `RenderLen` or `MeasuredText` document
+fn render_len_text<'d, 'a, T, A>(doc: &'d Doc<'a, T, A>) -> &'d str
+where
+    T: DocPtr<'a, A>,
+{
+    match *doc {
+        Doc::OwnedText(ref s) => s,
+        Doc::BorrowedText(s) => s,
+        Doc::SmallText(ref s) => s,
+        _ => unreachable!(),
+    }
+}
+
+/// Returns the number of tabs and spaces that `indent` is written as
+fn indent_parts(indent: Indent, indentation: Indentation) -> (usize, usize) {
+    let (tab_width, tabbed) = match indentation {
+        Indentation::Spaces | Indentation::Tabs(0) | Indentation::SmartTabs(0) => (1, 0),
+        Indentation::Tabs(tab_width) => (tab_width, indent.width),
+        Indentation::SmartTabs(tab_width) => (tab_width, indent.width - indent.align),
+    };
+    let tabs = tabbed / tab_width;
+    (tabs, indent.width - tabs * tab_width)
+}
+
+fn write_indent<W>(indent: Indent, indentation: Indentation, out: &mut W) -> Result<(), W::Error>
 where
     W: ?Sized + Render,
 {
-    out.write_str_all("\n")?;
-    write_spaces(ind, out)
+    let (tabs, spaces) = indent_parts(indent, indentation);
+    for _ in 0..tabs {
+        out.write_str_all("\t")?;
+    }
+    write_spaces(spaces, out)
 }
 
 fn write_spaces<W>(spaces: usize, out: &mut W) -> Result<(), W::Error>
@@ -331,26 +1010,125 @@ where
     Ok(())
 }
 
-struct Best<'d, 'a, T, A>
+/// The left side of a union which is being laid out until it is known whether it fits
+struct Attempt<'d, 'a, T, A>
+where
+    T: DocPtr<'a, A> + 'a,
+{
+    key: UnionKey<'a, T, A>,
+    // Laid out instead of the left side if it does not fit
+    right: Cmd<'d, 'a, T, A>,
+    pos: usize,
+    line_indent: Indent,
+    // The lengths of `bcmds` and `annotation_levels` when the attempt started
+    bcmds: usize,
+    annotation_levels: usize,
+    // `Best::scanned` of the enclosing attempt
+    scanned: usize,
+    // Where to roll the output (or `Best::buffer`) back to if the left side does not fit
+    checkpoint: usize,
+}
+
+struct Best<'d, 'a, T, A, Tr>
 where
     T: DocPtr<'a, A> + 'a,
 {
     pos: usize,
+    // The indentation of the line currently being written
+    line_indent: Indent,
     bcmds: Vec<Cmd<'d, 'a, T, A>>,
     fcmds: Vec<&'d Doc<'a, T, A>>,
     annotation_levels: Vec<usize>,
-    width: usize,
+    // The unions whose left side is being tried, innermost last
+    attempts: Vec<Attempt<'d, 'a, T, A>>,
+    // Holds the output while unions are being tried if the output can not be rolled back
+    buffer: Option<BufferWrite<'d, A>>,
+    // Whether the left side of a union was used, so that unions which are reached again in the
+    // same situation (such as nested `BlockDoc`s) do not need to be tried again
+    union_outcomes: HashMap<UnionKey<'a, T, A>, Vec<UnionOutcome<'a, T, A>>>,
+    // The lowest index of `bcmds` which has been looked at while trying the left side of the
+    // current union
+    scanned: usize,
+    options: &'d RenderOptions,
     temp_arena: &'d typed_arena::Arena<T>,
+    tracer: Tr,
 }
 
-impl<'d, 'a, T, A> Best<'d, 'a, T, A>
+impl<'d, 'a, T, A, Tr> Best<'d, 'a, T, A, Tr>
 where
     T: DocPtr<'a, A> + 'a,
+    Tr: LayoutTracer,
 {
-    fn fitting(&mut self, next: &'d Doc<'a, T, A>, mut pos: usize, ind: usize) -> bool {
+    fn line_width(&self) -> usize {
+        self.options.line_width(self.line_indent.width)
+    }
+
+    // Writing to the buffer can not fail so its errors are ignored
+    fn write_newline<O>(&mut self, ind: Indent, out: &mut O) -> Result<(), O::Error>
+    where
+        O: ?Sized + OutputAnnotated<'d, A>,
+    {
+        match &mut self.buffer {
+            Some(buffer) => {
+                let _ = buffer.write_newline(ind);
+            }
+            None => out.write_newline(ind)?,
+        }
+        self.pos = ind.width;
+        self.line_indent = ind;
+        Ok(())
+    }
+
+    /// Writes `s` and returns `false` if it makes the left side of the union being tried not fit
+    fn write_text<O>(&mut self, s: &str, len: usize, out: &mut O) -> Result<bool, O::Error>
+    where
+        O: ?Sized + OutputAnnotated<'d, A>,
+    {
+        if s.is_empty() {
+            return Ok(true);
+        }
+        match &mut self.buffer {
+            Some(buffer) => {
+                let _ = buffer.write_text(s, len);
+            }
+            None => out.write_text(s, len)?,
+        }
+        self.pos += len;
+        Ok(self.attempts.is_empty() || self.pos <= self.line_width())
+    }
+
+    fn push_annotation<O>(&mut self, annotation: &'d A, out: &mut O) -> Result<(), O::Error>
+    where
+        O: ?Sized + OutputAnnotated<'d, A>,
+    {
+        match &mut self.buffer {
+            Some(buffer) => {
+                let _ = buffer.push_annotation(annotation);
+            }
+            None => out.push_annotation(annotation)?,
+        }
+        Ok(())
+    }
+
+    fn pop_annotation<O>(&mut self, out: &mut O) -> Result<(), O::Error>
+    where
+        O: ?Sized + OutputAnnotated<'d, A>,
+    {
+        match &mut self.buffer {
+            Some(buffer) => {
+                let _ = buffer.pop_annotation();
+            }
+            None => out.pop_annotation()?,
+        }
+        Ok(())
+    }
+
+    fn fitting(&mut self, next: &'d Doc<'a, T, A>, mut pos: usize, ind: Indent) -> bool {
+        let width = self.line_width();
         let mut bidx = self.bcmds.len();
         self.fcmds.clear(); // clear from previous calls from best
         self.fcmds.push(next);
+        self.tracer.fitting();
 
         let mut mode = Mode::Flat;
         loop {
@@ -361,6 +1139,7 @@ where
                         return true;
                     } else {
                         bidx -= 1;
+                        self.scanned = self.scanned.min(bidx);
                         mode = Mode::Break;
                         self.bcmds[bidx].2
                     }
@@ -369,6 +1148,7 @@ where
             };
 
             loop {
+                self.tracer.fitting_doc();
                 match *doc {
                     Doc::Nil => {}
                     Doc::Append(ref ldoc, ref rdoc) => {
@@ -380,25 +1160,33 @@ where
                     Doc::Hardline => return mode == Mode::Break,
                     Doc::RenderLen(len, _) => {
                         pos += len;
-                        if pos > self.width {
+                        if pos > width {
+                            return false;
+                        }
+                    }
+                    Doc::MeasuredText(len, ref doc) => {
+                        pos += self
+                            .options
+                            .measured_text_width(len, render_len_text(doc), pos);
+                        if pos > width {
                             return false;
                         }
                     }
                     Doc::BorrowedText(str) => {
-                        pos += str.len();
-                        if pos > self.width {
+                        pos += self.options.text_width(str, pos);
+                        if pos > width {
                             return false;
                         }
                     }
                     Doc::OwnedText(ref str) => {
-                        pos += str.len();
-                        if pos > self.width {
+                        pos += self.options.text_width(str, pos);
+                        if pos > width {
                             return false;
                         }
                     }
                     Doc::SmallText(ref str) => {
-                        pos += str.len();
-                        if pos > self.width {
+                        pos += self.options.text_width(str, pos);
+                        if pos > width {
                             return false;
                         }
                     }
@@ -411,35 +1199,157 @@ where
                     }
 
                     Doc::Column(ref f) => {
+                        self.tracer.column_fn();
                         doc = self.temp_arena.alloc(f(pos));
                         continue;
                     }
                     Doc::Nesting(ref f) => {
-                        doc = self.temp_arena.alloc(f(ind));
+                        self.tracer.column_fn();
+                        doc = self.temp_arena.alloc(f(ind.width));
                         continue;
                     }
                     Doc::Nest(_, ref next)
+                    | Doc::Align(ref next)
                     | Doc::Group(ref next)
                     | Doc::Annotated(_, ref next)
                     | Doc::Union(_, ref next) => {
                         doc = next;
                         continue;
                     }
-                    Doc::Fail => return false,
+                    Doc::Fail | Doc::FailWith(_) => return false,
                 }
                 break;
             }
         }
     }
 
-    fn best<W>(&mut self, top: usize, out: &mut W) -> Result<bool, W::Error>
+    /// Looks up whether the left side of a union was used when it was last reached in the same
+    /// situation
+    fn union_outcome(&mut self, key: &UnionKey<'a, T, A>) -> Option<bool> {
+        let top = self.bcmds.len();
+        let bcmds = &self.bcmds;
+        let (rest, fits) = self.union_outcomes.get(key)?.iter().find(|(rest, _)| {
+            rest.len() <= top
+                && bcmds[top - rest.len()..]
+                    .iter()
+                    .zip(rest)
+                    .all(|(&(ind, mode, doc), &rest)| (ind, mode, doc as *const _) == rest)
+        })?;
+        // The outcome depends on the same commands as when it was found
+        self.scanned = self.scanned.min(top - rest.len());
+        Some(*fits)
+    }
+
+    /// Starts trying the left side of a union
+    fn start_attempt<O>(&mut self, key: UnionKey<'a, T, A>, right: Cmd<'d, 'a, T, A>, out: &mut O)
     where
-        W: RenderAnnotated<'d, A>,
-        W: ?Sized,
+        O: ?Sized + OutputAnnotated<'d, A>,
     {
-        let mut fits = true;
+        let checkpoint = match &mut self.buffer {
+            Some(buffer) => buffer.checkpoint(),
+            None => out.checkpoint(),
+        };
+        let checkpoint = checkpoint.unwrap_or_else(|| {
+            // Only the outermost union needs a buffer as the buffer itself can be rolled back
+            let mut buffer = BufferWrite::new();
+            let checkpoint = buffer.checkpoint().unwrap();
+            self.buffer = Some(buffer);
+            checkpoint
+        });
+
+        let bcmds = self.bcmds.len();
+        let scanned = mem::replace(&mut self.scanned, bcmds);
+        self.attempts.push(Attempt {
+            key,
+            right,
+            pos: self.pos,
+            line_indent: self.line_indent,
+            bcmds,
+            annotation_levels: self.annotation_levels.len(),
+            scanned,
+            checkpoint,
+        });
+    }
+
+    /// Stops trying the left side of the innermost union, remembering whether it fitted
+    fn end_attempt(&mut self, fits: bool) -> Attempt<'d, 'a, T, A> {
+        let attempt = self.attempts.pop().unwrap();
+        let buffered = self
+            .buffer
+            .as_ref()
+            .map_or(0, |buffer| buffer.len() - attempt.checkpoint);
+        self.tracer.union(buffered, !fits);
+
+        let rest = self.bcmds[self.scanned..attempt.bcmds]
+            .iter()
+            .map(|&(ind, mode, doc)| (ind, mode, doc as *const _))
+            .collect();
+        self.union_outcomes
+            .entry(attempt.key)
+            .or_default()
+            .push((rest, fits));
+        self.scanned = self.scanned.min(attempt.scanned);
+        attempt
+    }
 
-        while top < self.bcmds.len() {
+    /// Keeps the left side of the innermost union, which has been laid out and fits
+    fn commit_attempt<O>(&mut self, out: &mut O) -> Result<(), O::Error>
+    where
+        O: ?Sized + OutputAnnotated<'d, A>,
+    {
+        let attempt = self.end_attempt(true);
+        if self.buffer.is_none() {
+            out.commit(attempt.checkpoint);
+        } else if self.attempts.is_empty() {
+            self.buffer.take().unwrap().render(out)?;
+        }
+        Ok(())
+    }
+
+    /// Discards the left side of the innermost union, which does not fit, and returns the command
+    /// for its right side
+    fn backtrack<O>(&mut self, out: &mut O) -> Result<Cmd<'d, 'a, T, A>, O::Error>
+    where
+        O: ?Sized + OutputAnnotated<'d, A>,
+    {
+        let attempt = self.end_attempt(false);
+        match &mut self.buffer {
+            Some(_) if self.attempts.is_empty() => self.buffer = None,
+            Some(buffer) => {
+                let _ = buffer.rollback(attempt.checkpoint);
+            }
+            None => out.rollback(attempt.checkpoint)?,
+        }
+        self.pos = attempt.pos;
+        self.line_indent = attempt.line_indent;
+        self.bcmds.truncate(attempt.bcmds);
+        self.annotation_levels.truncate(attempt.annotation_levels);
+        Ok(attempt.right)
+    }
+
+    fn best<O>(&mut self, out: &mut O) -> Result<(), O::Error>
+    where
+        O: OutputAnnotated<'d, A>,
+        O: ?Sized,
+    {
+        loop {
+            // The commands of a union are laid out on the same stack as the rest of the document
+            // so the left side of the innermost union has been laid out once the stack is back to
+            // where it started
+            let top = match self.attempts.last() {
+                Some(attempt) if attempt.bcmds == self.bcmds.len() => {
+                    self.commit_attempt(out)?;
+                    continue;
+                }
+                Some(attempt) => attempt.bcmds,
+                None if out.is_done() => break,
+                None => 0,
+            };
+            if self.bcmds.len() == top {
+                break;
+            }
+
+            self.tracer.command(self.bcmds.len());
             let mut cmd = self.bcmds.pop().unwrap();
             loop {
                 let (ind, mode, doc) = cmd;
@@ -466,107 +1376,134 @@ where
                         continue;
                     }
                     Doc::Nest(off, ref doc) => {
-                        // Once https://doc.rust-lang.org/std/primitive.usize.html#method.saturating_add_signed is stable
-                        // this can be replaced
-                        let new_ind = if off >= 0 {
-                            ind.saturating_add(off as usize)
-                        } else {
-                            ind.saturating_sub(off.unsigned_abs())
-                        };
-                        cmd = (new_ind, mode, doc);
+                        cmd = (ind.nest(off), mode, doc);
+                        continue;
+                    }
+                    Doc::Align(ref doc) => {
+                        cmd = (ind.align(self.pos), mode, doc);
                         continue;
                     }
                     Doc::Hardline => {
                         // The next document may have different indentation so we should use it if
-                        // we can
-                        if let Some(next) = self.bcmds.pop() {
-                            write_newline(next.0, out)?;
-                            self.pos = next.0;
-                            cmd = next;
-                            continue;
-                        } else {
-                            write_newline(ind, out)?;
-                            self.pos = ind;
+                        // we can. A union may have been reached since `top` was computed, so the
+                        // commands of the innermost attempt are looked up again
+                        let len = self.bcmds.len();
+                        let top = self.attempts.last().map_or(0, |attempt| attempt.bcmds);
+                        match self.bcmds.last() {
+                            // Continuing with the next document would skip the end of the
+                            // annotation that this line break is the last part of, or lay out
+                            // the document after a union while trying its left side
+                            Some(next)
+                                if len <= top || self.annotation_levels.last() == Some(&len) =>
+                            {
+                                self.scanned = self.scanned.min(len - 1);
+                                self.write_newline(next.0, out)?;
+                            }
+                            Some(_) => {
+                                let next = self.bcmds.pop().unwrap();
+                                self.write_newline(next.0, out)?;
+                                cmd = next;
+                                continue;
+                            }
+                            None => self.write_newline(ind, out)?,
                         }
                     }
-                    Doc::RenderLen(len, ref doc) => match **doc {
-                        Doc::OwnedText(ref s) => {
-                            out.write_str_all(s)?;
-                            self.pos += len;
-                            fits &= self.pos <= self.width;
-                        }
-                        Doc::BorrowedText(s) => {
-                            out.write_str_all(s)?;
-                            self.pos += len;
-                            fits &= self.pos <= self.width;
+                    Doc::RenderLen(len, ref doc) => {
+                        if !self.write_text(render_len_text(doc), len, out)? {
+                            cmd = self.backtrack(out)?;
+                            continue;
                         }
-                        Doc::SmallText(ref s) => {
-                            out.write_str_all(s)?;
-                            self.pos += len;
-                            fits &= self.pos <= self.width;
+                    }
+                    Doc::MeasuredText(len, ref doc) => {
+                        let s = render_len_text(doc);
+                        let len = self.options.measured_text_width(len, s, self.pos);
+                        if !self.write_text(s, len, out)? {
+                            cmd = self.backtrack(out)?;
+                            continue;
                         }
-                        _ => unreachable!(),
-                    },
+                    }
                     Doc::OwnedText(ref s) => {
-                        out.write_str_all(s)?;
-                        self.pos += s.len();
-                        fits &= self.pos <= self.width;
+                        let len = self.options.text_width(s, self.pos);
+                        if !self.write_text(s, len, out)? {
+                            cmd = self.backtrack(out)?;
+                            continue;
+                        }
                     }
                     Doc::BorrowedText(s) => {
-                        out.write_str_all(s)?;
-                        self.pos += s.len();
-                        fits &= self.pos <= self.width;
+                        let len = self.options.text_width(s, self.pos);
+                        if !self.write_text(s, len, out)? {
+                            cmd = self.backtrack(out)?;
+                            continue;
+                        }
                     }
                     Doc::SmallText(ref s) => {
-                        out.write_str_all(s)?;
-                        self.pos += s.len();
-                        fits &= self.pos <= self.width;
+                        let len = self.options.text_width(s, self.pos);
+                        if !self.write_text(s, len, out)? {
+                            cmd = self.backtrack(out)?;
+                            continue;
+                        }
                     }
                     Doc::Annotated(ref ann, ref doc) => {
-                        out.push_annotation(ann)?;
+                        self.push_annotation(ann, out)?;
                         self.annotation_levels.push(self.bcmds.len());
                         cmd.2 = doc;
                         continue;
                     }
                     Doc::Union(ref l, ref r) => {
-                        let pos = self.pos;
-                        let annotation_levels = self.annotation_levels.len();
-                        let bcmds = self.bcmds.len();
-
-                        self.bcmds.push((ind, mode, l));
-
-                        let mut buffer = BufferWrite::new();
-
-                        match self.best(bcmds, &mut buffer) {
-                            Ok(true) => buffer.render(out)?,
-                            Ok(false) | Err(()) => {
-                                self.pos = pos;
-                                self.bcmds.truncate(bcmds);
-                                self.annotation_levels.truncate(annotation_levels);
+                        let key = (&**l as *const _, self.pos, self.line_indent, ind, mode);
+                        match self.union_outcome(&key) {
+                            // The left side is known to fit so it does not need to be buffered
+                            Some(true) => {
+                                cmd.2 = l;
+                                continue;
+                            }
+                            Some(false) => {
                                 cmd.2 = r;
                                 continue;
                             }
+                            None => (),
                         }
+
+                        self.start_attempt(key, (ind, mode, r), out);
+                        cmd.2 = l;
+                        continue;
                     }
                     Doc::Column(ref f) => {
+                        self.tracer.column_fn();
                         cmd.2 = self.temp_arena.alloc(f(self.pos));
                         continue;
                     }
                     Doc::Nesting(ref f) => {
-                        cmd.2 = self.temp_arena.alloc(f(ind));
+                        self.tracer.column_fn();
+                        cmd.2 = self.temp_arena.alloc(f(ind.width));
                         continue;
                     }
-                    Doc::Fail => return Err(out.fail_doc()),
+                    Doc::Fail | Doc::FailWith(_) if !self.attempts.is_empty() => {
+                        cmd = self.backtrack(out)?;
+                        continue;
+                    }
+                    // Outside of the left side of a union failures are written out in lenient mode
+                    Doc::Fail if self.options.lenient => {
+                        let options = self.options;
+                        let s = options.fail_text(None);
+                        self.write_text(s, options.measure(s, self.pos), out)?;
+                    }
+                    Doc::FailWith(ref message) if self.options.lenient => {
+                        let s = self.options.fail_text(Some(message));
+                        self.write_text(s, self.options.measure(s, self.pos), out)?;
+                    }
+                    Doc::Fail => return Err(out.fail_doc(None)),
+                    Doc::FailWith(ref message) => return Err(out.fail_doc(Some(message))),
                 }
 
                 break;
             }
             while self.annotation_levels.last() == Some(&self.bcmds.len()) {
                 self.annotation_levels.pop();
-                out.pop_annotation()?;
+                self.pop_annotation(out)?;
             }
         }
 
-        Ok(fits)
+        Ok(())
     }
 }
@@ -0,0 +1,119 @@
+//! Reporting why a document failed to render.
+
+use std::{error::Error, fmt, marker::PhantomData};
+
+use super::{Render, RenderAnnotated, Rewind};
+
+/// Describes a `fail` document which made rendering fail (see `RenderAnnotated::fail_doc_with`
+/// and `Doc::render_string`).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RenderError<A> {
+    message: Option<String>,
+    annotations: Vec<A>,
+}
+
+impl<A> RenderError<A> {
+    pub(super) fn new(message: Option<&str>, annotations: Vec<A>) -> Self {
+        RenderError {
+            message: message.map(String::from),
+            annotations,
+        }
+    }
+
+    /// The message given to `fail_with`, or `None` if the document was created with `fail`.
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    /// The annotations of the documents enclosing the failure, outermost first.
+    ///
+    /// The optimal layout algorithm does not keep track of the annotations, so this is always
+    /// empty when it is used.
+    pub fn annotations(&self) -> &[A] {
+        &self.annotations
+    }
+
+    /// Converts the annotations of the error with `f`.
+    pub fn map<B>(self, f: impl FnMut(A) -> B) -> RenderError<B> {
+        RenderError {
+            message: self.message,
+            annotations: self.annotations.into_iter().map(f).collect(),
+        }
+    }
+}
+
+impl<A> fmt::Display for RenderError<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Document failed to render")?;
+        if let Some(message) = &self.message {
+            write!(f, ": {}", message)?;
+        }
+        Ok(())
+    }
+}
+
+impl<A> Error for RenderError<A> where A: fmt::Debug {}
+
+/// Renders to a `String`, failing with a `RenderError` which owns its annotations
+pub(crate) struct StringWrite<A> {
+    upstream: String,
+    _annotation: PhantomData<fn(&A)>,
+}
+
+impl<A> StringWrite<A> {
+    pub(crate) fn new() -> Self {
+        StringWrite {
+            upstream: String::new(),
+            _annotation: PhantomData,
+        }
+    }
+
+    pub(crate) fn into_string(self) -> String {
+        self.upstream
+    }
+}
+
+impl<A> Render for StringWrite<A> {
+    type Error = RenderError<A>;
+
+    fn write_str(&mut self, s: &str) -> Result<usize, Self::Error> {
+        self.upstream.push_str(s);
+        Ok(s.len())
+    }
+
+    fn fail_doc(&self) -> Self::Error {
+        RenderError::new(None, Vec::new())
+    }
+
+    fn rewind(&mut self) -> Option<&mut dyn Rewind<Error = Self::Error>> {
+        Some(self)
+    }
+}
+
+impl<A> Rewind for StringWrite<A> {
+    fn checkpoint(&mut self) -> usize {
+        self.upstream.len()
+    }
+
+    fn rollback(&mut self, checkpoint: usize) -> Result<(), Self::Error> {
+        self.upstream.truncate(checkpoint);
+        Ok(())
+    }
+}
+
+impl<A> RenderAnnotated<'_, A> for StringWrite<A>
+where
+    A: Clone,
+{
+    fn push_annotation(&mut self, _: &A) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn pop_annotation(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn fail_doc_with(&self, error: RenderError<&A>) -> Self::Error {
+        error.map(A::clone)
+    }
+}
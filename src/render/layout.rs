@@ -0,0 +1,320 @@
+//! Recording the output of the layout algorithms so that it can be rendered later.
+
+use std::{fmt, slice};
+
+use super::{
+    FmtWrite, Indent, Output, OutputAnnotated, RenderAnnotated, RenderError, RenderOptions,
+    WriteState, Writer,
+};
+
+enum Entry<A> {
+    // Ends at the given offset in the text buffer and is the given number of columns wide
+    Text(usize, usize),
+    Newline(Indent),
+    PushAnnotation(A),
+    PopAnnotation,
+}
+
+struct Buffer<A> {
+    text: String,
+    entries: Vec<Entry<A>>,
+}
+
+impl<A> Buffer<A> {
+    fn new() -> Self {
+        Buffer {
+            text: String::new(),
+            entries: Vec::new(),
+        }
+    }
+
+    fn tokens(&self) -> Tokens<'_, A> {
+        Tokens {
+            text: &self.text,
+            start: 0,
+            entries: self.entries.iter(),
+        }
+    }
+
+    fn write_text(&mut self, s: &str, len: usize) {
+        self.text.push_str(s);
+        self.entries.push(Entry::Text(self.text.len(), len));
+    }
+
+    fn rollback(&mut self, checkpoint: usize) {
+        self.entries.truncate(checkpoint);
+        let end = self
+            .entries
+            .iter()
+            .rev()
+            .find_map(|entry| match entry {
+                Entry::Text(end, _) => Some(*end),
+                _ => None,
+            })
+            .unwrap_or(0);
+        self.text.truncate(end);
+    }
+}
+
+/// Replays the recorded output into `out`, using `annotation` to retrieve the annotations to
+/// push
+fn replay<'l, 'd, B, A, O>(
+    buffer: &'l Buffer<B>,
+    annotation: impl Fn(&'l B) -> &'d A,
+    out: &mut O,
+) -> Result<(), O::Error>
+where
+    A: 'd,
+    O: ?Sized + OutputAnnotated<'d, A>,
+{
+    let mut start = 0;
+    for entry in &buffer.entries {
+        match entry {
+            Entry::Text(end, len) => {
+                out.write_text(&buffer.text[start..*end], *len)?;
+                start = *end;
+            }
+            Entry::Newline(indent) => out.write_newline(*indent)?,
+            Entry::PushAnnotation(ann) => out.push_annotation(annotation(ann))?,
+            Entry::PopAnnotation => out.pop_annotation()?,
+        }
+    }
+    Ok(())
+}
+
+/// Buffers the output of a `union` until it is known whether it fits, for outputs which can not
+/// be rolled back
+pub(super) struct BufferWrite<'d, A>(Buffer<&'d A>);
+
+impl<'d, A> BufferWrite<'d, A> {
+    pub(super) fn new() -> Self {
+        BufferWrite(Buffer::new())
+    }
+
+    /// The number of pieces of output in the buffer
+    pub(super) fn len(&self) -> usize {
+        self.0.entries.len()
+    }
+
+    pub(super) fn render<O>(&self, out: &mut O) -> Result<(), O::Error>
+    where
+        O: ?Sized + OutputAnnotated<'d, A>,
+    {
+        replay(&self.0, |ann| *ann, out)
+    }
+}
+
+impl<A> Output for BufferWrite<'_, A> {
+    type Error = ();
+
+    fn write_text(&mut self, s: &str, len: usize) -> Result<(), Self::Error> {
+        self.0.write_text(s, len);
+        Ok(())
+    }
+
+    fn write_newline(&mut self, indent: Indent) -> Result<(), Self::Error> {
+        self.0.entries.push(Entry::Newline(indent));
+        Ok(())
+    }
+
+    fn fail_doc(&self, _: Option<&str>) -> Self::Error {}
+
+    fn checkpoint(&mut self) -> Option<usize> {
+        Some(self.len())
+    }
+
+    fn rollback(&mut self, checkpoint: usize) -> Result<(), Self::Error> {
+        self.0.rollback(checkpoint);
+        Ok(())
+    }
+}
+
+impl<'d, A> OutputAnnotated<'d, A> for BufferWrite<'d, A> {
+    fn push_annotation(&mut self, annotation: &'d A) -> Result<(), Self::Error> {
+        self.0.entries.push(Entry::PushAnnotation(annotation));
+        Ok(())
+    }
+
+    fn pop_annotation(&mut self) -> Result<(), Self::Error> {
+        self.0.entries.push(Entry::PopAnnotation);
+        Ok(())
+    }
+}
+
+/// A part of a `Layout`.
+#[derive(Debug, Eq, PartialEq)]
+pub enum LayoutToken<'l, A> {
+    /// Text which is written as is.
+    Text(&'l str),
+    /// A line break. If any text is written on the next line it is preceded by `indent` columns
+    /// of indentation, the last `align` of which come from `align` (and are therefore always
+    /// written as spaces when using `Indentation::SmartTabs`).
+    Newline {
+        indent: usize,
+        align: usize,
+    },
+    PushAnnotation(&'l A),
+    PopAnnotation,
+}
+
+/// Iterator over the tokens of a `Layout`.
+pub struct Tokens<'l, A> {
+    text: &'l str,
+    start: usize,
+    entries: slice::Iter<'l, Entry<A>>,
+}
+
+impl<'l, A> Iterator for Tokens<'l, A> {
+    type Item = LayoutToken<'l, A>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(match self.entries.next()? {
+            Entry::Text(end, _) => {
+                let s = &self.text[self.start..*end];
+                self.start = *end;
+                LayoutToken::Text(s)
+            }
+            Entry::Newline(indent) => LayoutToken::Newline {
+                indent: indent.width,
+                align: indent.align,
+            },
+            Entry::PushAnnotation(ann) => LayoutToken::PushAnnotation(ann),
+            Entry::PopAnnotation => LayoutToken::PopAnnotation,
+        })
+    }
+}
+
+/// A document which has been laid out but not yet written anywhere.
+///
+/// Laying out a document is the expensive part of rendering it, so a `Layout` can be used to
+/// write the same document to several backends. `tokens` exposes the layout itself so that
+/// backends which do not fit `RenderAnnotated` can consume it directly.
+///
+/// ```
+/// use pretty::{Arena, DocAllocator, LayoutToken, RenderOptions};
+///
+/// let arena = Arena::<&str>::new();
+/// let doc = arena
+///     .text("fn")
+///     .annotate("keyword")
+///     .append(arena.line().append("main()").nest(4))
+///     .group();
+/// let layout = doc.layout_with(&RenderOptions::new(6)).unwrap();
+///
+/// assert_eq!(layout.to_string(), "fn\n    main()");
+/// assert_eq!(
+///     layout.tokens().collect::<Vec<_>>(),
+///     [
+///         LayoutToken::PushAnnotation(&"keyword"),
+///         LayoutToken::Text("fn"),
+///         LayoutToken::PopAnnotation,
+///         LayoutToken::Newline { indent: 4, align: 0 },
+///         LayoutToken::Text("main()"),
+///     ],
+/// );
+/// ```
+pub struct Layout<A> {
+    buffer: Buffer<A>,
+    options: RenderOptions,
+}
+
+impl<A> Layout<A> {
+    pub(super) fn new(options: RenderOptions) -> Self {
+        Layout {
+            buffer: Buffer::new(),
+            options,
+        }
+    }
+
+    /// Returns the tokens which make up the layout.
+    pub fn tokens(&self) -> Tokens<'_, A> {
+        self.buffer.tokens()
+    }
+
+    /// Returns the tokens which make up the layout along with the number of columns each of
+    /// them occupies (zero for anything but text).
+    pub(crate) fn measured_tokens(&self) -> impl Iterator<Item = (LayoutToken<'_, A>, usize)> {
+        self.buffer
+            .tokens()
+            .zip(self.buffer.entries.iter().map(|entry| match entry {
+                Entry::Text(_, len) => *len,
+                _ => 0,
+            }))
+    }
+
+    /// Writes the layout to a `RenderAnnotated<A>` object, using the output related options
+    /// (such as the newline and indentation) that it was laid out with.
+    pub fn render<W>(&self, out: &mut W) -> Result<(), W::Error>
+    where
+        W: ?Sized,
+        for<'b> W: RenderAnnotated<'b, A>,
+    {
+        let mut state = WriteState::new();
+        let mut writer = Writer::new(&self.options, out, &mut state);
+        replay(&self.buffer, |ann| ann, &mut writer)
+    }
+}
+
+impl<A> fmt::Display for Layout<A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.render(&mut FmtWrite::new(f))
+    }
+}
+
+impl<A> Output for Layout<A>
+where
+    A: Clone,
+{
+    type Error = RenderError<A>;
+
+    fn write_text(&mut self, s: &str, len: usize) -> Result<(), Self::Error> {
+        self.buffer.write_text(s, len);
+        Ok(())
+    }
+
+    fn write_newline(&mut self, indent: Indent) -> Result<(), Self::Error> {
+        self.buffer.entries.push(Entry::Newline(indent));
+        Ok(())
+    }
+
+    fn fail_doc(&self, message: Option<&str>) -> Self::Error {
+        // The annotations which are open at the end of the layout
+        let mut annotations = Vec::new();
+        for entry in &self.buffer.entries {
+            match entry {
+                Entry::PushAnnotation(ann) => annotations.push(ann.clone()),
+                Entry::PopAnnotation => {
+                    annotations.pop();
+                }
+                Entry::Text(..) | Entry::Newline(_) => (),
+            }
+        }
+        RenderError::new(message, annotations)
+    }
+
+    fn checkpoint(&mut self) -> Option<usize> {
+        Some(self.buffer.entries.len())
+    }
+
+    fn rollback(&mut self, checkpoint: usize) -> Result<(), Self::Error> {
+        self.buffer.rollback(checkpoint);
+        Ok(())
+    }
+}
+
+impl<A> OutputAnnotated<'_, A> for Layout<A>
+where
+    A: Clone,
+{
+    fn push_annotation(&mut self, annotation: &A) -> Result<(), Self::Error> {
+        self.buffer
+            .entries
+            .push(Entry::PushAnnotation(annotation.clone()));
+        Ok(())
+    }
+
+    fn pop_annotation(&mut self) -> Result<(), Self::Error> {
+        self.buffer.entries.push(Entry::PopAnnotation);
+        Ok(())
+    }
+}
@@ -0,0 +1,515 @@
+//! A layout algorithm which picks the cheapest layout according to a `CostFunction` instead of
+//! greedily deciding each group with one line of lookahead.
+//!
+//! The algorithm resolves every document, at a given column, indentation and mode, into the set
+//! of its Pareto optimal layouts (no other layout ends at an earlier column for less cost) and
+//! memoises the result, as described in "A pretty expressive printer" (Porncharoenwase, Pombrio
+//! and Torlak) which builds on Bernardy's "A Pretty But Not Greedy Printer".
+
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{Doc, DocPtr};
+
+use super::{render_len_text, Indent, Mode, OutputAnnotated, RenderOptions};
+
+/// Assigns a cost to layouts for the optimal layout algorithm (see `RenderOptions::optimal`).
+///
+/// The cost of a layout is the sum of the costs of all its text fragments and line breaks.
+pub trait CostFunction {
+    /// The cost of writing text which is `len` columns wide at `column` on a page which is
+    /// `width` columns wide
+    fn text(&self, width: usize, column: usize, len: usize) -> usize;
+
+    /// The cost of a line break followed by `indent` columns of indentation
+    fn newline(&self, indent: usize) -> usize;
+}
+
+/// The default `CostFunction`.
+///
+/// Each column of text which overflows the page width is penalized quadratically (by
+/// `overflow_penalty` times the square of the overflow) and each line break by `newline_penalty`.
+///
+/// ```
+/// use pretty::{Arena, DefaultCost, DocAllocator, RenderOptions};
+///
+/// let arena = Arena::<()>::new();
+/// let body = arena.text("abc").append(arena.hardline()).append("1234567890").align();
+/// let doc = arena.text("key:").append(arena.softline()).append(body);
+///
+/// // The greedy layout only looks at the first line of `body` when deciding the `softline`
+/// assert_eq!(doc.1.pretty(12).to_string(), "key: abc\n     1234567890");
+/// assert_eq!(
+///     doc.1.pretty_with(RenderOptions::new(12).optimal(DefaultCost::new())).to_string(),
+///     "key:\nabc\n1234567890",
+/// );
+/// ```
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DefaultCost {
+    overflow_penalty: usize,
+    newline_penalty: usize,
+}
+
+impl Default for DefaultCost {
+    fn default() -> Self {
+        DefaultCost::new()
+    }
+}
+
+impl DefaultCost {
+    pub fn new() -> Self {
+        DefaultCost {
+            overflow_penalty: 100,
+            newline_penalty: 1,
+        }
+    }
+
+    /// Sets the penalty for each squared column of overflow. Defaults to 100.
+    pub fn overflow_penalty(mut self, overflow_penalty: usize) -> Self {
+        self.overflow_penalty = overflow_penalty;
+        self
+    }
+
+    /// Sets the penalty for each line break. Defaults to 1.
+    pub fn newline_penalty(mut self, newline_penalty: usize) -> Self {
+        self.newline_penalty = newline_penalty;
+        self
+    }
+}
+
+impl CostFunction for DefaultCost {
+    fn text(&self, width: usize, column: usize, len: usize) -> usize {
+        let end = column.saturating_add(len);
+        if end <= width {
+            0
+        } else {
+            // Only count the overflow which this text is responsible for
+            let overflow = end - column.max(width);
+            overflow
+                .saturating_mul(overflow)
+                .saturating_mul(self.overflow_penalty)
+        }
+    }
+
+    fn newline(&self, _indent: usize) -> usize {
+        self.newline_penalty
+    }
+}
+
+enum Layout<'d, A> {
+    Text(&'d str, usize),
+    Newline(Indent),
+    Push(&'d A),
+    Pop,
+    Concat(LayoutId, LayoutId),
+}
+
+// Layouts are stored in a `Vec` and referred to by their index so that long sequences do not
+// need to be dropped recursively
+type LayoutId = usize;
+
+#[derive(Clone, Copy)]
+struct Measure {
+    // The column the layout ends at
+    last: usize,
+    cost: usize,
+    layout: Option<LayoutId>,
+    // A line break which ends the layout. It is indented by the document that follows it (like
+    // `Best` does) so it is not part of `layout` until that document is known. If nothing
+    // follows, the contained indentation is used.
+    pending: Option<Indent>,
+}
+
+impl Measure {
+    fn empty(column: usize) -> Self {
+        Measure {
+            last: column,
+            cost: 0,
+            layout: None,
+            pending: None,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.layout.is_none() && self.pending.is_none()
+    }
+}
+
+/// Removes all measures which are dominated by another measure, that is, the other measure ends
+/// at the same or an earlier column and costs at most as much. Earlier measures are preferred
+/// when they are equally good.
+fn prune(measures: &mut Vec<Measure>) {
+    measures.sort_by_key(|m| (m.pending.is_some(), m.last, m.cost));
+    let mut best = None;
+    measures.retain(|m| {
+        let key = m.pending.is_some();
+        match best {
+            Some((best_key, best_cost)) if best_key == key && best_cost <= m.cost => false,
+            _ => {
+                best = Some((key, m.cost));
+                true
+            }
+        }
+    });
+}
+
+// The measures of a document only depend on the column it starts at, its indentation and mode
+type MemoKey<'a, T, A> = (*const Doc<'a, T, A>, usize, Indent, Mode);
+
+struct Optimal<'d, 'a, T, A>
+where
+    T: DocPtr<'a, A> + 'a,
+{
+    options: &'d RenderOptions,
+    cost: &'d dyn CostFunction,
+    memo: HashMap<MemoKey<'a, T, A>, Rc<[Measure]>>,
+    layouts: Vec<Layout<'d, A>>,
+    temp_arena: &'d typed_arena::Arena<T>,
+    // The message of the first `fail` document which was reached
+    failure: Option<&'d str>,
+}
+
+impl<'d, 'a, T, A> Optimal<'d, 'a, T, A>
+where
+    T: DocPtr<'a, A> + 'a,
+{
+    fn layout(&mut self, layout: Layout<'d, A>) -> Option<LayoutId> {
+        self.layouts.push(layout);
+        Some(self.layouts.len() - 1)
+    }
+
+    fn concat_layout(&mut self, l: Option<LayoutId>, r: Option<LayoutId>) -> Option<LayoutId> {
+        match (l, r) {
+            (Some(l), Some(r)) => self.layout(Layout::Concat(l, r)),
+            (l, None) => l,
+            (None, r) => r,
+        }
+    }
+
+    fn text(&mut self, s: &'d str, len: usize, column: usize) -> Rc<[Measure]> {
+        let cost = self.cost.text(self.options.width, column, len);
+        let layout = self.layout(Layout::Text(s, len));
+        Rc::new([Measure {
+            last: column + len,
+            cost,
+            layout,
+            pending: None,
+        }])
+    }
+
+    fn resolve(
+        &mut self,
+        doc: &'d Doc<'a, T, A>,
+        column: usize,
+        ind: Indent,
+        mode: Mode,
+    ) -> Rc<[Measure]> {
+        let key = (doc as *const _, column, ind, mode);
+        if let Some(measures) = self.memo.get(&key) {
+            return measures.clone();
+        }
+
+        let measures: Rc<[Measure]> = match *doc {
+            Doc::Nil => Rc::new([Measure::empty(column)]),
+            Doc::Append(..) => self.sequence(doc, column, ind, mode).into(),
+            Doc::Group(ref doc) => match mode {
+                Mode::Flat => self.resolve(doc, column, ind, mode),
+                Mode::Break => {
+                    let mut measures = self.resolve(doc, column, ind, Mode::Flat).to_vec();
+                    measures.extend_from_slice(&self.resolve(doc, column, ind, Mode::Break));
+                    prune(&mut measures);
+                    measures.into()
+                }
+            },
+            Doc::FlatAlt(ref b, ref f) => {
+                let doc = match mode {
+                    Mode::Break => b,
+                    Mode::Flat => f,
+                };
+                self.resolve(doc, column, ind, mode)
+            }
+            Doc::Nest(off, ref doc) => self.resolve(doc, column, ind.nest(off), mode),
+            Doc::Align(ref doc) => self.resolve(doc, column, ind.align(column), mode),
+            Doc::Hardline => match mode {
+                // Like `Best`, a hard line break prevents a group from being flattened
+                Mode::Flat => Rc::new([]),
+                Mode::Break => Rc::new([Measure {
+                    last: ind.width,
+                    cost: 0,
+                    layout: None,
+                    pending: Some(ind),
+                }]),
+            },
+            Doc::RenderLen(len, ref doc) => self.text(render_len_text(doc), len, column),
+            Doc::MeasuredText(len, ref doc) => {
+                let s = render_len_text(doc);
+                self.text(s, self.options.measured_text_width(len, s, column), column)
+            }
+            Doc::OwnedText(ref s) => self.text(s, self.options.text_width(s, column), column),
+            Doc::BorrowedText(s) => self.text(s, self.options.text_width(s, column), column),
+            Doc::SmallText(ref s) => self.text(s, self.options.text_width(s, column), column),
+            Doc::Annotated(ref ann, ref doc) => {
+                let measures = self.resolve(doc, column, ind, mode);
+                measures
+                    .iter()
+                    .map(|m| {
+                        let push = self.layout(Layout::Push(ann));
+                        let pop = self.layout(Layout::Pop);
+                        let inner = self.concat_layout(m.layout, pop);
+                        Measure {
+                            layout: self.concat_layout(push, inner),
+                            ..*m
+                        }
+                    })
+                    .collect()
+            }
+            Doc::Union(ref l, ref r) => {
+                // Failing layouts of the left side lose ties with the right side, like in the
+                // greedy algorithm
+                let (mut measures, failed): (Vec<_>, Vec<_>) = self
+                    .resolve(l, column, ind, mode)
+                    .iter()
+                    .cloned()
+                    .partition(|m| m.cost != usize::MAX);
+                measures.extend_from_slice(&self.resolve(r, column, ind, mode));
+                measures.extend(failed);
+                prune(&mut measures);
+                measures.into()
+            }
+            Doc::Column(ref f) => {
+                let doc = self.temp_arena.alloc(f(column));
+                self.resolve(doc, column, ind, mode)
+            }
+            Doc::Nesting(ref f) => {
+                let doc = self.temp_arena.alloc(f(ind.width));
+                self.resolve(doc, column, ind, mode)
+            }
+            // In lenient mode failing is allowed, but only chosen if there is no other layout
+            Doc::Fail | Doc::FailWith(_) if self.options.lenient => {
+                let options = self.options;
+                let message = match *doc {
+                    Doc::FailWith(ref message) => Some(&message[..]),
+                    _ => None,
+                };
+                let s = options.fail_text(message);
+                let len = options.measure(s, column);
+                let layout = self.layout(Layout::Text(s, len));
+                Rc::new([Measure {
+                    last: column + len,
+                    cost: usize::MAX,
+                    layout,
+                    pending: None,
+                }])
+            }
+            Doc::Fail => Rc::new([]),
+            Doc::FailWith(ref message) => {
+                if self.failure.is_none() {
+                    self.failure = Some(message);
+                }
+                Rc::new([])
+            }
+        };
+
+        self.memo.insert(key, measures.clone());
+        measures
+    }
+
+    fn sequence(
+        &mut self,
+        doc: &'d Doc<'a, T, A>,
+        column: usize,
+        ind: Indent,
+        mode: Mode,
+    ) -> Vec<Measure> {
+        let mut docs = Vec::new();
+        let mut stack = vec![doc];
+        while let Some(doc) = stack.pop() {
+            match *doc {
+                Doc::Append(ref l, ref r) => {
+                    stack.push(r);
+                    stack.push(l);
+                }
+                _ => docs.push(doc),
+            }
+        }
+
+        let mut measures = vec![Measure::empty(column)];
+        for doc in docs {
+            let mut next = Vec::new();
+            for m in &measures {
+                let column = if m.pending.is_some() {
+                    ind.width
+                } else {
+                    m.last
+                };
+                for n in self.resolve(doc, column, ind, mode).iter() {
+                    next.push(self.concat(m, n, ind));
+                }
+            }
+            prune(&mut next);
+            measures = next;
+            if measures.is_empty() {
+                break;
+            }
+        }
+        measures
+    }
+
+    fn concat(&mut self, l: &Measure, r: &Measure, ind: Indent) -> Measure {
+        match l.pending {
+            // Wait for some actual text before deciding the indentation of the line break
+            Some(_) if r.is_empty() => *l,
+            Some(_) => {
+                let newline = self.layout(Layout::Newline(ind));
+                let layout = self.concat_layout(l.layout, newline);
+                Measure {
+                    last: r.last,
+                    cost: l
+                        .cost
+                        .saturating_add(self.cost.newline(ind.width))
+                        .saturating_add(r.cost),
+                    layout: self.concat_layout(layout, r.layout),
+                    pending: r.pending,
+                }
+            }
+            None => Measure {
+                last: r.last,
+                cost: l.cost.saturating_add(r.cost),
+                layout: self.concat_layout(l.layout, r.layout),
+                pending: r.pending,
+            },
+        }
+    }
+}
+
+pub(super) fn best<'d, 'a, O, T, A>(
+    doc: &'d Doc<'a, T, A>,
+    options: &'d RenderOptions,
+    cost: &'d dyn CostFunction,
+    temp_arena: &'d typed_arena::Arena<T>,
+    out: &mut O,
+) -> Result<(), O::Error>
+where
+    T: DocPtr<'a, A> + 'a,
+    O: ?Sized + OutputAnnotated<'d, A>,
+{
+    let mut optimal = Optimal {
+        options,
+        cost,
+        memo: HashMap::new(),
+        layouts: Vec::new(),
+        temp_arena,
+        failure: None,
+    };
+    let measures = optimal.resolve(doc, 0, Indent::default(), Mode::Break);
+    let best = measures.iter().min_by_key(|m| match m.pending {
+        Some(ind) => m.cost.saturating_add(cost.newline(ind.width)),
+        None => m.cost,
+    });
+    let layout = match best {
+        Some(m) => match m.pending {
+            Some(ind) => {
+                let newline = optimal.layout(Layout::Newline(ind));
+                optimal.concat_layout(m.layout, newline)
+            }
+            None => m.layout,
+        },
+        None => return Err(out.fail_doc(optimal.failure)),
+    };
+
+    let layouts = &optimal.layouts;
+    let mut stack: Vec<LayoutId> = layout.into_iter().collect();
+    while let Some(layout) = stack.pop() {
+        if out.is_done() {
+            break;
+        }
+        match layouts[layout] {
+            Layout::Text(s, len) => {
+                if !s.is_empty() {
+                    out.write_text(s, len)?;
+                }
+            }
+            Layout::Newline(ind) => out.write_newline(ind)?,
+            Layout::Push(ann) => out.push_annotation(ann)?,
+            Layout::Pop => out.pop_annotation()?,
+            Layout::Concat(l, r) => {
+                stack.push(r);
+                stack.push(l);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Arena, BoxDoc, DefaultCost, DocAllocator, RenderOptions};
+
+    fn optimal(width: usize) -> RenderOptions {
+        RenderOptions::new(width).optimal(DefaultCost::new())
+    }
+
+    #[test]
+    fn same_as_greedy_when_greedy_fits() {
+        let arena = Arena::<()>::new();
+        let doc = arena
+            .text("(")
+            .append(
+                arena
+                    .line_()
+                    .append(arena.intersperse(["a,", "b,", "c"].iter().cloned(), arena.line()))
+                    .nest(2),
+            )
+            .append(arena.line_())
+            .append(")")
+            .group();
+
+        for &width in &[3, 9, 80] {
+            assert_eq!(
+                doc.1.pretty_with(optimal(width)).to_string(),
+                doc.1.pretty(width).to_string(),
+            );
+        }
+    }
+
+    #[test]
+    fn line_break_uses_indentation_of_next_document() {
+        let doc: BoxDoc<()> = BoxDoc::text("a")
+            .append(BoxDoc::hardline())
+            .nest(2)
+            .append("b")
+            .append(BoxDoc::hardline().append("c").nest(2))
+            .annotate(());
+
+        assert_eq!(doc.pretty_with(optimal(80)).to_string(), "a\nb\n  c");
+    }
+
+    #[test]
+    fn union_picks_cheapest_side() {
+        let arena = Arena::<()>::new();
+        let doc = arena
+            .text("123456")
+            .union(arena.text("123").append(arena.hardline()).append("456"));
+
+        assert_eq!(doc.1.pretty_with(optimal(6)).to_string(), "123456");
+        assert_eq!(doc.1.pretty_with(optimal(4)).to_string(), "123\n456");
+    }
+
+    #[test]
+    fn fail_everywhere_is_an_error() {
+        let doc: BoxDoc<()> = BoxDoc::text("a").append(BoxDoc::fail());
+
+        let mut s = String::new();
+        assert!(doc.render_fmt_with(&optimal(80), &mut s).is_err());
+    }
+
+    #[test]
+    fn stress_append() {
+        let arena = Arena::<()>::new();
+        let mut doc = arena.nil();
+        for _ in 0..10000 {
+            doc = doc.append("a").append(arena.softline());
+        }
+        let s = doc.1.pretty_with(optimal(80)).to_string();
+        assert!(s.lines().all(|line| line.len() <= 80));
+    }
+}
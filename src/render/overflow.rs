@@ -0,0 +1,159 @@
+//! Reporting the lines which do not fit in the page width.
+
+use super::{Cursor, Render, RenderAnnotated, RenderError, RenderOptions};
+
+/// A line of the rendered output which is wider than the page.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Overflow<A> {
+    /// The line, starting at 0.
+    pub line: usize,
+    /// The width of the whole line, in columns.
+    pub width: usize,
+    /// The column that `text` starts at.
+    pub column: usize,
+    /// The first piece of text on the line which extends past the page width.
+    pub text: String,
+    /// The annotations of the documents enclosing `text`, outermost first.
+    pub annotations: Vec<A>,
+}
+
+/// Wraps a `RenderAnnotated` and records an `Overflow` for every line which is wider than the
+/// width of `options`. Text is measured like the renderer measures it with the same `options`.
+///
+/// ```
+/// use pretty::{Arena, DocAllocator, FmtWrite, OverflowWriter, RenderOptions};
+///
+/// let arena = Arena::<&str>::new();
+/// let doc = arena
+///     .text("let")
+///     .append(arena.line())
+///     .append(arena.text("a_very_long_identifier").annotate("ident"))
+///     .append(arena.line())
+///     .append("= 1;")
+///     .group();
+///
+/// let options = RenderOptions::new(10);
+/// let mut s = String::new();
+/// let mut writer = OverflowWriter::new(FmtWrite::new(&mut s), &options);
+/// doc.render_raw_with(&options, &mut writer).unwrap();
+/// let overflows = writer.into_overflows();
+///
+/// assert_eq!(s, "let\na_very_long_identifier\n= 1;");
+/// assert_eq!(overflows.len(), 1);
+/// assert_eq!(overflows[0].line, 1);
+/// assert_eq!(overflows[0].width, 22);
+/// assert_eq!(overflows[0].text, "a_very_long_identifier");
+/// assert_eq!(overflows[0].annotations, ["ident"]);
+/// ```
+pub struct OverflowWriter<W, A> {
+    upstream: W,
+    options: RenderOptions,
+    line: usize,
+    overflows: Vec<Overflow<A>>,
+    // Set while the last entry of `overflows` is on the current line
+    overflowing: bool,
+    annotations: Vec<A>,
+}
+
+impl<W, A> OverflowWriter<W, A> {
+    pub fn new(upstream: W, options: &RenderOptions) -> Self {
+        OverflowWriter {
+            upstream,
+            options: options.clone(),
+            line: 0,
+            overflows: Vec::new(),
+            overflowing: false,
+            annotations: Vec::new(),
+        }
+    }
+
+    /// Returns the overflows recorded so far. The width of an overflowing line is only final
+    /// once the line has ended.
+    pub fn overflows(&self) -> &[Overflow<A>] {
+        &self.overflows
+    }
+
+    pub fn into_overflows(self) -> Vec<Overflow<A>> {
+        self.overflows
+    }
+}
+
+impl<W, A> Render for OverflowWriter<W, A>
+where
+    W: Render,
+    A: Clone,
+{
+    type Error = W::Error;
+
+    fn write_str(&mut self, s: &str) -> Result<usize, W::Error> {
+        self.upstream.write_str(s)
+    }
+
+    fn write_str_all(&mut self, s: &str) -> Result<(), W::Error> {
+        self.upstream.write_str_all(s)
+    }
+
+    fn write_text(&mut self, s: &str, cursor: Cursor) -> Result<(), W::Error> {
+        self.upstream.write_text(s, cursor)?;
+        let end = cursor.column + self.options.measure(s, cursor.column);
+        if self.overflowing {
+            if let Some(overflow) = self.overflows.last_mut() {
+                overflow.width = end;
+            }
+        } else if end > self.options.width {
+            self.overflowing = true;
+            self.overflows.push(Overflow {
+                line: self.line,
+                width: end,
+                column: cursor.column,
+                text: s.into(),
+                annotations: self.annotations.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    fn newline(&mut self, cursor: Cursor) -> Result<(), W::Error> {
+        self.line += 1;
+        self.overflowing = false;
+        self.upstream.newline(cursor)
+    }
+
+    fn fail_doc(&self) -> Self::Error {
+        self.upstream.fail_doc()
+    }
+}
+
+impl<'a, W, A> RenderAnnotated<'a, A> for OverflowWriter<W, A>
+where
+    W: RenderAnnotated<'a, A>,
+    A: Clone,
+{
+    fn push_annotation(&mut self, annotation: &'a A) -> Result<(), Self::Error> {
+        self.upstream.push_annotation(annotation)?;
+        self.annotations.push(annotation.clone());
+        Ok(())
+    }
+
+    fn pop_annotation(&mut self) -> Result<(), Self::Error> {
+        self.upstream.pop_annotation()?;
+        self.annotations.pop();
+        Ok(())
+    }
+
+    fn push_annotation_at(&mut self, annotation: &'a A, cursor: Cursor) -> Result<(), Self::Error> {
+        self.upstream.push_annotation_at(annotation, cursor)?;
+        self.annotations.push(annotation.clone());
+        Ok(())
+    }
+
+    fn pop_annotation_at(&mut self, cursor: Cursor) -> Result<(), Self::Error> {
+        self.upstream.pop_annotation_at(cursor)?;
+        self.annotations.pop();
+        Ok(())
+    }
+
+    fn fail_doc_with(&self, error: RenderError<&'a A>) -> Self::Error {
+        self.upstream.fail_doc_with(error)
+    }
+}
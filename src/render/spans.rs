@@ -0,0 +1,173 @@
+//! Recording where annotated documents end up in the rendered output.
+
+use super::{Cursor, Render, RenderAnnotated, RenderError};
+
+/// A position in the rendered output.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Position {
+    /// The line, starting at 0.
+    pub line: usize,
+    /// The byte offset from the start of the line.
+    pub column: usize,
+    /// The byte offset from the start of the output.
+    pub offset: usize,
+}
+
+/// The part of the rendered output which an annotated document occupies. `end` is exclusive.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Span<A> {
+    pub annotation: A,
+    pub start: Position,
+    pub end: Position,
+}
+
+/// Wraps a `RenderAnnotated` and records a `Span` for every annotation pushed to it.
+///
+/// Annotations start after the indentation of the line they are pushed on, so the span of an
+/// annotated document covers exactly the text that it rendered.
+///
+/// ```
+/// use pretty::{Arena, DocAllocator, FmtWrite, Position, SpanWriter};
+///
+/// let arena = Arena::<&str>::new();
+/// let doc = arena
+///     .text("f(")
+///     .append(arena.hardline().append(arena.text("x").annotate("arg")).nest(4))
+///     .append(arena.hardline())
+///     .append(")")
+///     .annotate("call");
+///
+/// let mut s = String::new();
+/// let mut writer = SpanWriter::new(FmtWrite::new(&mut s));
+/// doc.render_raw(80, &mut writer).unwrap();
+/// let spans = writer.into_spans();
+///
+/// assert_eq!(s, "f(\n    x\n)");
+/// assert_eq!(spans[0].annotation, "call");
+/// assert_eq!(spans[1].annotation, "arg");
+/// assert_eq!(spans[1].start, Position { line: 1, column: 4, offset: 7 });
+/// assert_eq!(spans[1].end, Position { line: 1, column: 5, offset: 8 });
+/// assert_eq!(&s[spans[1].start.offset..spans[1].end.offset], "x");
+/// ```
+pub struct SpanWriter<W, A> {
+    upstream: W,
+    position: Position,
+    spans: Vec<Span<A>>,
+    // Indices into `spans` of the annotations which have not been popped yet
+    open: Vec<usize>,
+}
+
+impl<W, A> SpanWriter<W, A> {
+    pub fn new(upstream: W) -> Self {
+        SpanWriter {
+            upstream,
+            position: Position::default(),
+            spans: Vec::new(),
+            open: Vec::new(),
+        }
+    }
+
+    /// Returns the spans recorded so far, in the order their annotations were pushed.
+    pub fn spans(&self) -> &[Span<A>] {
+        &self.spans
+    }
+
+    pub fn into_spans(self) -> Vec<Span<A>> {
+        self.spans
+    }
+
+    fn advance(&mut self, s: &str) {
+        self.position.offset += s.len();
+        match s.rfind('\n') {
+            Some(last) => {
+                self.position.line += s.matches('\n').count();
+                self.position.column = s.len() - last - 1;
+            }
+            None => self.position.column += s.len(),
+        }
+    }
+}
+
+impl<W, A> Render for SpanWriter<W, A>
+where
+    W: Render,
+{
+    type Error = W::Error;
+
+    fn write_str(&mut self, s: &str) -> Result<usize, W::Error> {
+        let written = self.upstream.write_str(s)?;
+        self.advance(&s[..written]);
+        Ok(written)
+    }
+
+    fn write_str_all(&mut self, s: &str) -> Result<(), W::Error> {
+        self.upstream.write_str_all(s)?;
+        self.advance(s);
+        Ok(())
+    }
+
+    fn write_text(&mut self, s: &str, cursor: Cursor) -> Result<(), W::Error> {
+        self.upstream.write_text(s, cursor)?;
+        self.advance(s);
+        Ok(())
+    }
+
+    fn newline(&mut self, cursor: Cursor) -> Result<(), W::Error> {
+        self.upstream.newline(cursor)
+    }
+
+    fn fail_doc(&self) -> Self::Error {
+        self.upstream.fail_doc()
+    }
+}
+
+impl<W, A> SpanWriter<W, A> {
+    fn open_span(&mut self, annotation: A) {
+        self.open.push(self.spans.len());
+        self.spans.push(Span {
+            annotation,
+            start: self.position,
+            end: self.position,
+        });
+    }
+
+    fn close_span(&mut self) {
+        if let Some(i) = self.open.pop() {
+            self.spans[i].end = self.position;
+        }
+    }
+}
+
+impl<'a, W, A> RenderAnnotated<'a, A> for SpanWriter<W, A>
+where
+    W: RenderAnnotated<'a, A>,
+    A: Clone,
+{
+    fn push_annotation(&mut self, annotation: &'a A) -> Result<(), Self::Error> {
+        self.upstream.push_annotation(annotation)?;
+        self.open_span(annotation.clone());
+        Ok(())
+    }
+
+    fn pop_annotation(&mut self) -> Result<(), Self::Error> {
+        self.upstream.pop_annotation()?;
+        self.close_span();
+        Ok(())
+    }
+
+    fn push_annotation_at(&mut self, annotation: &'a A, cursor: Cursor) -> Result<(), Self::Error> {
+        self.upstream.push_annotation_at(annotation, cursor)?;
+        self.open_span(annotation.clone());
+        Ok(())
+    }
+
+    fn pop_annotation_at(&mut self, cursor: Cursor) -> Result<(), Self::Error> {
+        self.upstream.pop_annotation_at(cursor)?;
+        self.close_span();
+        Ok(())
+    }
+
+    fn fail_doc_with(&self, error: RenderError<&'a A>) -> Self::Error {
+        self.upstream.fail_doc_with(error)
+    }
+}
@@ -0,0 +1,474 @@
+//! An incremental renderer in the style of Oppen's pretty printer.
+//!
+//! Documents are converted into a stream of tokens which are buffered only until the layout of
+//! the outermost undecided group is known. Since a group is known not to fit as soon as the
+//! buffered text exceeds the remaining width of the line, the buffer stays proportional to the
+//! page width instead of the size of the document.
+
+use std::collections::VecDeque;
+
+use crate::{Doc, DocPtr};
+
+use super::{
+    render_len_text, Indent, Mode, RenderAnnotated, RenderError, RenderOptions, WriteState, Writer,
+};
+
+enum Token<A> {
+    // The width is only known up front if it was given by a `RenderLen`, or stored in a
+    // `MeasuredText` and the default width measure is used, otherwise it depends on the column
+    // the text ends up at
+    Text(String, Option<usize>),
+    Hardline,
+    GroupBegin,
+    GroupEnd,
+    // `FlatAlt` is streamed as `AltBreak <break doc> AltFlat <flat doc> AltEnd`
+    AltBreak,
+    AltFlat,
+    AltEnd,
+    NestBegin(isize),
+    AlignBegin,
+    IndentEnd,
+    AnnotationBegin(A),
+    AnnotationEnd,
+}
+
+enum Frame<'d, 'a, T, A>
+where
+    T: DocPtr<'a, A>,
+{
+    Doc(&'d Doc<'a, T, A>),
+    Token(Token<A>),
+}
+
+/// Skips tokens until the end of the current side of a `FlatAlt`. Returns the index after the
+/// `AltFlat` (or `AltEnd`) which ends it, or `None` if that token has not been streamed yet.
+fn skip_alt<A>(tokens: &VecDeque<Token<A>>, mut i: usize) -> Option<usize> {
+    let mut depth = 0;
+    loop {
+        match tokens.get(i)? {
+            Token::AltBreak => depth += 1,
+            Token::AltFlat if depth == 0 => return Some(i + 1),
+            Token::AltEnd if depth == 0 => return Some(i + 1),
+            Token::AltEnd => depth -= 1,
+            _ => (),
+        }
+        i += 1;
+    }
+}
+
+/// Renders documents incrementally, writing out each part of the output as soon as the layout
+/// of it is known.
+///
+/// The output is the same as rendering all pushed documents appended together with
+/// `Doc::render_raw_with`, as long as they do not contain `union`, `column` or `nesting` (and the
+/// combinators built on them, such as `width`). Those need to inspect or backtrack over an
+/// unbounded part of the output, so pushing them results in an error. `align` (and `hang` and
+/// `indent`) are supported.
+///
+/// ```
+/// use pretty::{Arena, DocAllocator, IoWrite, RenderOptions, StreamRender};
+///
+/// let arena = Arena::<()>::new();
+/// let mut out = Vec::new();
+/// let mut stream = StreamRender::new(RenderOptions::new(10), IoWrite::new(&mut out));
+/// for i in 0..3 {
+///     let entry = arena.text(format!("entry{}:", i)).append(arena.line()).append("ok");
+///     stream.push(&entry.group().append(arena.hardline())).unwrap();
+/// }
+/// stream.finish().unwrap();
+/// assert_eq!(String::from_utf8(out).unwrap(), "entry0: ok\nentry1: ok\nentry2: ok\n");
+/// ```
+pub struct StreamRender<W, A = ()> {
+    options: RenderOptions,
+    out: W,
+    tokens: VecDeque<Token<A>>,
+    state: WriteState<A>,
+    pos: usize,
+    line_indent: Indent,
+    // A line break is due but the indentation of the next line depends on the tokens which
+    // follows it
+    newline_pending: bool,
+    // Annotations which ended after the pending line break
+    pending_pops: usize,
+    modes: Vec<Mode>,
+    indents: Vec<Indent>,
+    // Set while skipping the side of a `FlatAlt` which was not chosen
+    skipping: bool,
+    skip_depth: usize,
+}
+
+impl<W, A> StreamRender<W, A>
+where
+    for<'b> W: RenderAnnotated<'b, A>,
+    A: Clone,
+{
+    pub fn new(options: RenderOptions, out: W) -> Self {
+        StreamRender {
+            options,
+            out,
+            tokens: VecDeque::new(),
+            state: WriteState::new(),
+            pos: 0,
+            line_indent: Indent::default(),
+            newline_pending: false,
+            pending_pops: 0,
+            modes: vec![Mode::Break],
+            indents: vec![Indent::default()],
+            skipping: false,
+            skip_depth: 0,
+        }
+    }
+
+    /// Appends `doc` to the output, writing everything that can be laid out without seeing more
+    /// of the document.
+    pub fn push<'a, T>(&mut self, doc: &Doc<'a, T, A>) -> Result<(), W::Error>
+    where
+        T: DocPtr<'a, A> + 'a,
+    {
+        let mut stack = vec![Frame::Doc(doc)];
+        while let Some(frame) = stack.pop() {
+            // The output was cut short by `RenderOptions::max_lines` or `max_bytes`
+            if self.state.is_truncated() {
+                break;
+            }
+            let doc = match frame {
+                Frame::Doc(doc) => doc,
+                Frame::Token(token) => {
+                    self.token(token)?;
+                    continue;
+                }
+            };
+            match *doc {
+                Doc::Nil => (),
+                Doc::Append(ref l, ref r) => {
+                    stack.push(Frame::Doc(r));
+                    stack.push(Frame::Doc(l));
+                }
+                Doc::Group(ref doc) => {
+                    self.token(Token::GroupBegin)?;
+                    stack.push(Frame::Token(Token::GroupEnd));
+                    stack.push(Frame::Doc(doc));
+                }
+                Doc::FlatAlt(ref b, ref f) => {
+                    self.token(Token::AltBreak)?;
+                    stack.push(Frame::Token(Token::AltEnd));
+                    stack.push(Frame::Doc(f));
+                    stack.push(Frame::Token(Token::AltFlat));
+                    stack.push(Frame::Doc(b));
+                }
+                Doc::Nest(off, ref doc) => {
+                    self.token(Token::NestBegin(off))?;
+                    stack.push(Frame::Token(Token::IndentEnd));
+                    stack.push(Frame::Doc(doc));
+                }
+                Doc::Align(ref doc) => {
+                    self.token(Token::AlignBegin)?;
+                    stack.push(Frame::Token(Token::IndentEnd));
+                    stack.push(Frame::Doc(doc));
+                }
+                Doc::Hardline => self.token(Token::Hardline)?,
+                Doc::RenderLen(len, ref doc) => {
+                    self.token(Token::Text(render_len_text(doc).into(), Some(len)))?;
+                }
+                Doc::MeasuredText(len, ref doc) => {
+                    let s = render_len_text(doc);
+                    self.token(Token::Text(s.into(), self.options.measured_len(len)))?;
+                }
+                Doc::OwnedText(ref s) => self.token(Token::Text(s[..].into(), None))?,
+                Doc::BorrowedText(s) => self.token(Token::Text(s.into(), None))?,
+                Doc::SmallText(ref s) => self.token(Token::Text(s[..].into(), None))?,
+                Doc::Annotated(ref ann, ref doc) => {
+                    self.token(Token::AnnotationBegin(ann.clone()))?;
+                    stack.push(Frame::Token(Token::AnnotationEnd));
+                    stack.push(Frame::Doc(doc));
+                }
+                Doc::Union(..) => return Err(self.fail_doc(Some("`union` can not be streamed"))),
+                Doc::Column(_) | Doc::Nesting(_) => {
+                    return Err(self.fail_doc(Some("`column` and `nesting` can not be streamed")))
+                }
+                Doc::Fail if self.options.lenient => {
+                    let marker = self.options.fail_text(None).to_string();
+                    self.token(Token::Text(marker, None))?;
+                }
+                Doc::FailWith(ref message) if self.options.lenient => {
+                    self.token(Token::Text(message.to_string(), None))?;
+                }
+                Doc::Fail => return Err(self.fail_doc(None)),
+                Doc::FailWith(ref message) => return Err(self.fail_doc(Some(message))),
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes out the rest of the document and returns the writer.
+    pub fn finish(mut self) -> Result<W, W::Error> {
+        self.flush(true)?;
+        if self.newline_pending {
+            let ind = *self.indents.last().unwrap();
+            self.write_newline(ind)?;
+        }
+        Ok(self.out)
+    }
+
+    /// Creates the error for a document which can not be rendered, inside the annotations of
+    /// both the written and the buffered part of the output
+    fn fail_doc(&self, message: Option<&str>) -> W::Error {
+        let mut annotations: Vec<&A> = self.state.annotations().collect();
+        annotations.truncate(annotations.len() - self.pending_pops);
+        for token in &self.tokens {
+            match token {
+                Token::AnnotationBegin(ann) => annotations.push(ann),
+                Token::AnnotationEnd => {
+                    annotations.pop();
+                }
+                _ => (),
+            }
+        }
+        self.out
+            .fail_doc_with(RenderError::new(message, annotations))
+    }
+
+    fn writer(&mut self) -> Writer<'_, W, A, A> {
+        Writer::new(&self.options, &mut self.out, &mut self.state)
+    }
+
+    /// Writes the pending line break, followed by the annotations which ended after it
+    fn write_newline(&mut self, ind: Indent) -> Result<(), W::Error> {
+        self.newline_pending = false;
+        self.pos = ind.width;
+        self.line_indent = ind;
+        let pops = std::mem::take(&mut self.pending_pops);
+        let mut writer = self.writer();
+        writer.write_newline(ind)?;
+        for _ in 0..pops {
+            writer.pop_annotation()?;
+        }
+        Ok(())
+    }
+
+    fn token(&mut self, token: Token<A>) -> Result<(), W::Error> {
+        self.tokens.push_back(token);
+        self.flush(false)
+    }
+
+    /// Decides if the group whose `GroupBegin` is at the front of the buffer fits on the current
+    /// line. Returns `None` if more tokens are needed to decide.
+    fn fits(&self, finished: bool) -> Option<bool> {
+        let width = self.options.line_width(self.line_indent.width);
+        let mut pos = self.pos;
+        let mut mode = Mode::Flat;
+        let mut depth = 0;
+        let mut i = 1;
+        loop {
+            let token = match self.tokens.get(i) {
+                Some(token) => token,
+                // All of the document has been processed
+                None if finished => return Some(true),
+                None => return None,
+            };
+            i += 1;
+            match *token {
+                Token::Text(ref s, len) => {
+                    pos += len.unwrap_or_else(|| self.options.text_width(s, pos));
+                    if pos > width {
+                        return Some(false);
+                    }
+                }
+                // Newlines inside the group makes it not fit, but those outside lets it
+                // fit on the current line
+                Token::Hardline => return Some(mode == Mode::Break),
+                Token::GroupBegin => depth += 1,
+                Token::GroupEnd => {
+                    if depth == 0 {
+                        mode = Mode::Break;
+                    } else {
+                        depth -= 1;
+                    }
+                }
+                Token::AltBreak => {
+                    if mode == Mode::Flat {
+                        i = skip_alt(&self.tokens, i)?;
+                    }
+                }
+                Token::AltFlat => i = skip_alt(&self.tokens, i)?,
+                Token::AltEnd
+                | Token::NestBegin(_)
+                | Token::AlignBegin
+                | Token::IndentEnd
+                | Token::AnnotationBegin(_)
+                | Token::AnnotationEnd => (),
+            }
+        }
+    }
+
+    fn flush(&mut self, finished: bool) -> Result<(), W::Error> {
+        while let Some(token) = self.tokens.front() {
+            if self.state.is_truncated() {
+                self.tokens.clear();
+                break;
+            }
+            if self.skipping {
+                match token {
+                    Token::AltBreak => self.skip_depth += 1,
+                    Token::AltFlat | Token::AltEnd if self.skip_depth == 0 => self.skipping = false,
+                    Token::AltEnd => self.skip_depth -= 1,
+                    _ => (),
+                }
+                self.tokens.pop_front();
+                continue;
+            }
+
+            let mode = *self.modes.last().unwrap();
+            let ind = *self.indents.last().unwrap();
+            let is_group_begin = matches!(token, Token::GroupBegin);
+            if self.newline_pending {
+                match token {
+                    Token::GroupEnd
+                    | Token::AltFlat
+                    | Token::AltEnd
+                    | Token::IndentEnd
+                    | Token::AnnotationEnd => (),
+                    // The next document may have different indentation so we should use it
+                    _ => self.write_newline(ind)?,
+                }
+            }
+
+            if is_group_begin {
+                let mode = match mode {
+                    Mode::Flat => Mode::Flat,
+                    Mode::Break => match self.fits(finished) {
+                        Some(true) => Mode::Flat,
+                        Some(false) => Mode::Break,
+                        None => return Ok(()),
+                    },
+                };
+                self.modes.push(mode);
+                self.tokens.pop_front();
+                continue;
+            }
+
+            match self.tokens.pop_front().unwrap() {
+                Token::GroupBegin => unreachable!(),
+                Token::GroupEnd => {
+                    self.modes.pop();
+                }
+                Token::AltBreak => {
+                    if mode == Mode::Flat {
+                        self.skipping = true;
+                    }
+                }
+                // Reached the end of the break side of the `FlatAlt` so skip the flat side
+                Token::AltFlat => self.skipping = true,
+                Token::AltEnd => (),
+                Token::NestBegin(off) => self.indents.push(ind.nest(off)),
+                Token::AlignBegin => self.indents.push(ind.align(self.pos)),
+                Token::IndentEnd => {
+                    self.indents.pop();
+                }
+                Token::Hardline => {
+                    self.pos = ind.width;
+                    self.newline_pending = true;
+                }
+                Token::Text(s, len) => {
+                    let len = len.unwrap_or_else(|| self.options.text_width(&s, self.pos));
+                    if !s.is_empty() {
+                        self.writer().write_text(&s, len)?;
+                    }
+                    self.pos += len;
+                }
+                Token::AnnotationBegin(ann) => self.writer().push_annotation(ann)?,
+                Token::AnnotationEnd => {
+                    if self.newline_pending {
+                        self.pending_pops += 1;
+                    } else {
+                        self.writer().pop_annotation()?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{tests::TestWriter, Arena, DocAllocator, DocBuilder, FmtWrite};
+
+    fn check<'a>(doc: DocBuilder<'a, Arena<'a, ()>, ()>) {
+        for width in [0, 5, 10, 20, 40, 80] {
+            for options in [
+                RenderOptions::new(width),
+                RenderOptions::new(width).ribbon_width(8),
+                RenderOptions::new(width).indentation(crate::Indentation::SmartTabs(4)),
+                RenderOptions::new(width).max_lines(3),
+                RenderOptions::new(width).max_bytes(20),
+            ] {
+                let mut expected = String::new();
+                doc.render_raw_with(&options, &mut TestWriter::new(FmtWrite::new(&mut expected)))
+                    .unwrap();
+
+                let mut actual = String::new();
+                let mut stream =
+                    StreamRender::new(options.clone(), TestWriter::new(FmtWrite::new(&mut actual)));
+                stream.push(&doc).unwrap();
+                stream.finish().unwrap();
+                assert_eq!(actual, expected, "{:?}", options);
+            }
+        }
+    }
+
+    #[test]
+    fn same_as_best() {
+        let arena = Arena::<()>::new();
+        let list = |n| {
+            arena
+                .intersperse(
+                    (0..n).map(|i| arena.text(format!("item{}", i)).annotate(())),
+                    arena.text(",").append(arena.line()),
+                )
+                .nest(2)
+                .append(arena.line_().flat_alt(arena.text(";")))
+                .group()
+        };
+        check(list(3));
+        check(
+            arena
+                .text("let x =")
+                .append(arena.line().append(list(5)).nest(4))
+                .group(),
+        );
+        check(
+            arena
+                .text("fn(")
+                .append(list(4).align())
+                .append(")")
+                .append(arena.hardline())
+                .append(list(2).hang(2))
+                .append(arena.hardline())
+                .append(arena.line().append(list(6)).group().indent(3)),
+        );
+    }
+
+    #[test]
+    fn multiple_pushes() {
+        let arena = Arena::<()>::new();
+        let mut out = String::new();
+        let mut stream = StreamRender::new(RenderOptions::new(8), FmtWrite::new(&mut out));
+        stream
+            .push(&arena.text("aaa").append(arena.line()).nest(2).group())
+            .unwrap();
+        stream.push(&arena.text("bbbbbbbb")).unwrap();
+        stream.finish().unwrap();
+        assert_eq!(out, "aaa\nbbbbbbbb");
+    }
+
+    #[test]
+    fn union_is_an_error() {
+        let doc = crate::BoxDoc::<()>::text("a").union("b");
+        let mut out = String::new();
+        let mut stream = StreamRender::new(RenderOptions::new(8), FmtWrite::new(&mut out));
+        assert!(stream.push(&doc).is_err());
+    }
+}
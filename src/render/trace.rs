@@ -0,0 +1,121 @@
+//! Observing the work done by the greedy layout algorithm.
+
+/// Receives events from the greedy layout algorithm, to find out why laying out a document is
+/// slow (see `Doc::render_raw_traced`).
+///
+/// Every method does nothing by default. Rendering without a tracer uses `()`, whose calls
+/// compile to nothing.
+pub trait LayoutTracer {
+    /// Called when checking if a group fits on the rest of the line.
+    fn fitting(&mut self) {}
+
+    /// Called for every document which is scanned while checking if a group fits.
+    fn fitting_doc(&mut self) {}
+
+    /// Called for every command which is taken from the stack of documents waiting to be laid
+    /// out. `depth` is the size of the stack before the command was taken.
+    fn command(&mut self, depth: usize) {
+        let _ = depth;
+    }
+
+    /// Called after the left side of a union has been tried, which took `buffered` pieces of
+    /// buffered output (none if the output can be rolled back, see `Render::rewind`).
+    /// `backtracked` is `true` if it did not fit so the right side is used.
+    fn union(&mut self, buffered: usize, backtracked: bool) {
+        let _ = (buffered, backtracked);
+    }
+
+    /// Called when the function of a `column` or `nesting` document is evaluated.
+    fn column_fn(&mut self) {}
+}
+
+impl LayoutTracer for () {}
+
+impl<T> LayoutTracer for &mut T
+where
+    T: ?Sized + LayoutTracer,
+{
+    fn fitting(&mut self) {
+        (**self).fitting()
+    }
+
+    fn fitting_doc(&mut self) {
+        (**self).fitting_doc()
+    }
+
+    fn command(&mut self, depth: usize) {
+        (**self).command(depth)
+    }
+
+    fn union(&mut self, buffered: usize, backtracked: bool) {
+        (**self).union(buffered, backtracked)
+    }
+
+    fn column_fn(&mut self) {
+        (**self).column_fn()
+    }
+}
+
+/// A `LayoutTracer` which counts the events of the greedy layout algorithm.
+///
+/// ```
+/// use pretty::{Arena, DocAllocator, FmtWrite, LayoutStats, RenderOptions};
+///
+/// let arena = Arena::<()>::new();
+/// let doc = arena.text("a").append(arena.line()).append("b").group();
+///
+/// let mut stats = LayoutStats::default();
+/// let mut s = String::new();
+/// doc.render_raw_traced(&RenderOptions::new(80), &mut FmtWrite::new(&mut s), &mut stats)
+///     .unwrap();
+/// assert_eq!(s, "a b");
+/// assert_eq!(stats.fitting_calls, 1);
+/// assert_eq!(stats.union_backtracks, 0);
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct LayoutStats {
+    /// The number of times a group was checked to fit.
+    pub fitting_calls: usize,
+    /// The number of documents scanned while checking if groups fit.
+    pub fitting_docs: usize,
+    /// The number of commands taken from the stack of documents waiting to be laid out.
+    pub commands: usize,
+    /// The largest size of the stack of documents waiting to be laid out.
+    pub max_stack_depth: usize,
+    /// The number of unions whose left side was tried.
+    pub unions: usize,
+    /// The number of unions which used their right side because the left side did not fit.
+    pub union_backtracks: usize,
+    /// The largest number of pieces of output buffered for the left side of a union, when the
+    /// output can not be rolled back.
+    pub max_union_buffer: usize,
+    /// The number of times the function of a `column` or `nesting` document was evaluated.
+    pub column_fns: usize,
+}
+
+impl LayoutTracer for LayoutStats {
+    fn fitting(&mut self) {
+        self.fitting_calls += 1;
+    }
+
+    fn fitting_doc(&mut self) {
+        self.fitting_docs += 1;
+    }
+
+    fn command(&mut self, depth: usize) {
+        self.commands += 1;
+        self.max_stack_depth = self.max_stack_depth.max(depth);
+    }
+
+    fn union(&mut self, buffered: usize, backtracked: bool) {
+        self.unions += 1;
+        if backtracked {
+            self.union_backtracks += 1;
+        }
+        self.max_union_buffer = self.max_union_buffer.max(buffered);
+    }
+
+    fn column_fn(&mut self) {
+        self.column_fns += 1;
+    }
+}
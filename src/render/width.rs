@@ -0,0 +1,207 @@
+//! Measuring how many columns text takes up.
+
+use std::cmp;
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// Measures the width of text, in columns (see `RenderOptions::width_measure`).
+///
+/// Closures taking the text and the column it starts at can be used as measures.
+///
+/// ```
+/// use pretty::{Arena, DocAllocator, RenderOptions};
+///
+/// let arena = Arena::<()>::new();
+/// let doc = arena.text("aa").append(arena.line()).append("bb").group();
+///
+/// // Count every character as two columns
+/// let options = RenderOptions::new(8).width_measure(|s: &str, _: usize| 2 * s.chars().count());
+/// assert_eq!(doc.1.pretty_with(options).to_string(), "aa\nbb");
+/// ```
+pub trait WidthMeasure {
+    /// Returns the number of columns `text` takes up when it is written starting at `column`.
+    fn width(&self, text: &str, column: usize) -> usize;
+}
+
+impl<F> WidthMeasure for F
+where
+    F: Fn(&str, usize) -> usize,
+{
+    fn width(&self, text: &str, column: usize) -> usize {
+        self(text, column)
+    }
+}
+
+/// Measures text by the East Asian width of its characters (see the `unicode-width` crate).
+/// This is the default measure.
+///
+/// ```
+/// use pretty::{UnicodeWidth, WidthMeasure};
+///
+/// assert_eq!(UnicodeWidth::new().width("日本", 0), 4);
+/// assert_eq!(UnicodeWidth::new().width("±", 0), 1);
+/// assert_eq!(UnicodeWidth::new().ambiguous_wide(true).width("±", 0), 2);
+/// assert_eq!(UnicodeWidth::new().tab_width(4).width("a\tb", 2), 3);
+/// ```
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct UnicodeWidth {
+    ambiguous_wide: bool,
+    graphemes: bool,
+    tab_width: Option<usize>,
+}
+
+impl UnicodeWidth {
+    pub fn new() -> Self {
+        UnicodeWidth::default()
+    }
+
+    /// Treats characters whose East Asian width is ambiguous as wide, like terminals in CJK
+    /// locales do.
+    pub fn ambiguous_wide(mut self, ambiguous_wide: bool) -> Self {
+        self.ambiguous_wide = ambiguous_wide;
+        self
+    }
+
+    /// Measures each grapheme cluster on its own and counts it as at most 2 columns, like
+    /// terminals which render emoji sequences as a single glyph do.
+    pub fn graphemes(mut self, graphemes: bool) -> Self {
+        self.graphemes = graphemes;
+        self
+    }
+
+    /// Expands tabs to the next multiple of `tab_width` columns.
+    pub fn tab_width(mut self, tab_width: usize) -> Self {
+        self.tab_width = Some(tab_width).filter(|&tab_width| tab_width != 0);
+        self
+    }
+
+    fn str_width(&self, s: &str) -> usize {
+        if s.is_ascii() {
+            return s.len();
+        }
+        let width = |s: &str| {
+            if self.ambiguous_wide {
+                s.width_cjk()
+            } else {
+                s.width()
+            }
+        };
+        if self.graphemes {
+            s.graphemes(true).map(|g| cmp::min(width(g), 2)).sum()
+        } else {
+            width(s)
+        }
+    }
+}
+
+impl WidthMeasure for UnicodeWidth {
+    fn width(&self, text: &str, column: usize) -> usize {
+        match self.tab_width {
+            Some(tab_width) => {
+                let mut end = column;
+                for (i, s) in text.split('\t').enumerate() {
+                    if i != 0 {
+                        end += tab_width - end % tab_width;
+                    }
+                    end += self.str_width(s);
+                }
+                end - column
+            }
+            None => self.str_width(text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{Arena, DocAllocator, RenderOptions};
+
+    #[test]
+    fn graphemes() {
+        let measure = UnicodeWidth::new().graphemes(true);
+        assert_eq!(measure.width("e\u{301}", 0), 1);
+        assert_eq!(measure.width("🇸🇪🇳🇴", 0), 4);
+        assert_eq!(measure.width("a\u{1F44D}\u{1F3FD}b", 0), 4);
+    }
+
+    #[test]
+    fn tabs_depend_on_the_column() {
+        let arena = Arena::<()>::new();
+        let doc = arena
+            .text("a")
+            .append(arena.line())
+            .append("\tb")
+            .group()
+            .nest(2);
+        let doc = arena.text("x").append(arena.hardline()).append(doc);
+
+        let options = RenderOptions::new(9).width_measure(UnicodeWidth::new().tab_width(8));
+        assert_eq!(doc.1.pretty_with(options.clone()).to_string(), "x\na \tb");
+        assert_eq!(
+            doc.1
+                .pretty_with(options.clone().optimal(crate::DefaultCost::new()))
+                .to_string(),
+            "x\na \tb"
+        );
+        let options = RenderOptions::new(8).width_measure(UnicodeWidth::new().tab_width(8));
+        assert_eq!(doc.1.pretty_with(options).to_string(), "x\na\n  \tb");
+    }
+
+    #[test]
+    fn stored_widths_are_measured_again_by_custom_measures() {
+        let arena = Arena::<()>::new();
+        let doc = arena.text("日本");
+        // The width for the default measure is stored so that it does not need to be measured
+        // each time the document is looked at
+        assert!(matches!(*doc.1, crate::Doc::MeasuredText(4, _)));
+
+        let doc = doc.append(arena.line()).append("日本").group();
+        assert_eq!(doc.1.pretty(9).to_string(), "日本 日本");
+
+        let wide = |s: &str, _: usize| 3 * s.chars().count();
+        for options in [
+            RenderOptions::new(9).width_measure(wide),
+            RenderOptions::new(9)
+                .width_measure(wide)
+                .optimal(crate::DefaultCost::new()),
+        ] {
+            assert_eq!(doc.1.pretty_with(options).to_string(), "日本\n日本");
+        }
+    }
+
+    #[test]
+    fn render_len_is_used_by_custom_measures() {
+        let arena = Arena::<()>::new();
+        // Escape codes take up no columns on the terminal
+        let color = crate::DocBuilder(
+            &arena,
+            crate::Doc::RenderLen(0, arena.text("\x1b[31m").into_doc()).into(),
+        );
+        let doc = arena
+            .text("ab")
+            .append(arena.line())
+            .append(color)
+            .append("cd")
+            .group();
+
+        let measure = |s: &str, _: usize| s.chars().count();
+        for options in [
+            RenderOptions::new(5).width_measure(measure),
+            RenderOptions::new(5)
+                .width_measure(measure)
+                .optimal(crate::DefaultCost::new()),
+        ] {
+            assert_eq!(
+                doc.1.pretty_with(options.clone()).to_string(),
+                "ab \x1b[31mcd"
+            );
+
+            let mut stream = crate::StreamRender::new(options, crate::render::StringWrite::new());
+            stream.push(&doc).unwrap();
+            assert_eq!(stream.finish().unwrap().into_string(), "ab \x1b[31mcd");
+        }
+    }
+}
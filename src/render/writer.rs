@@ -0,0 +1,373 @@
+//! Writing the output of the layout algorithms to a `RenderAnnotated`.
+
+use std::{borrow::Borrow, cmp, marker::PhantomData, mem};
+
+use super::{
+    indent_parts, write_indent, Cursor, Indent, Output, OutputAnnotated, RenderAnnotated,
+    RenderError, RenderOptions, SPACES,
+};
+
+/// The state of a `Writer` which persists between uses of it. `P` is the type of the annotations
+/// which are held on to while the indentation of a line is pending.
+pub(super) struct WriteState<P> {
+    column: usize,
+    // The indentation of the line currently being written
+    line_indent: Indent,
+    // Indentation is only written once some text appears on the line so that empty lines do not
+    // end up with trailing whitespace
+    indent_pending: bool,
+    // Likewise, spaces at the end of a text are only written once more text follows them
+    spaces_pending: usize,
+    // Annotations pushed while the indentation or spaces are pending are pushed after them, so
+    // that they start at the text they annotate
+    pending_annotations: Vec<P>,
+    // The annotations which have been pushed to the output but not popped
+    open_annotations: Vec<P>,
+    lines: usize,
+    // Set once a line break is reached after `max_lines` lines, the output is only cut short if
+    // any text follows it
+    line_limit_reached: bool,
+    bytes: usize,
+    truncated: bool,
+    // The checkpoints of the output which may be rolled back to, along with the state to return
+    // to, innermost last
+    checkpoints: Vec<(usize, WriteState<P>)>,
+}
+
+impl<P> WriteState<P> {
+    pub(super) fn new() -> Self {
+        WriteState {
+            column: 0,
+            line_indent: Indent::default(),
+            indent_pending: true,
+            spaces_pending: 0,
+            pending_annotations: Vec::new(),
+            open_annotations: Vec::new(),
+            lines: 1,
+            line_limit_reached: false,
+            bytes: 0,
+            truncated: false,
+            checkpoints: Vec::new(),
+        }
+    }
+
+    /// Copies the state, except for its checkpoints
+    fn snapshot(&self) -> Self
+    where
+        P: Clone,
+    {
+        WriteState {
+            column: self.column,
+            line_indent: self.line_indent,
+            indent_pending: self.indent_pending,
+            spaces_pending: self.spaces_pending,
+            pending_annotations: self.pending_annotations.clone(),
+            open_annotations: self.open_annotations.clone(),
+            lines: self.lines,
+            line_limit_reached: self.line_limit_reached,
+            bytes: self.bytes,
+            truncated: self.truncated,
+            checkpoints: Vec::new(),
+        }
+    }
+
+    /// Returns true once the output has been cut short by `max_lines` or `max_bytes`
+    pub(super) fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// The annotations which are open at the current position, outermost first
+    pub(super) fn annotations(&self) -> impl Iterator<Item = &P> {
+        self.open_annotations
+            .iter()
+            .chain(&self.pending_annotations)
+    }
+}
+
+/// Writes a layout to a `RenderAnnotated`, applying the output related `RenderOptions`
+pub(super) struct Writer<'o, W: ?Sized, P, A> {
+    out: &'o mut W,
+    options: &'o RenderOptions,
+    state: &'o mut WriteState<P>,
+    _annotation: PhantomData<fn(&A)>,
+}
+
+impl<'o, W, P, A> Writer<'o, W, P, A>
+where
+    W: ?Sized,
+    for<'b> W: RenderAnnotated<'b, A>,
+    P: Borrow<A>,
+{
+    pub(super) fn new(
+        options: &'o RenderOptions,
+        out: &'o mut W,
+        state: &'o mut WriteState<P>,
+    ) -> Self {
+        Writer {
+            out,
+            options,
+            state,
+            _annotation: PhantomData,
+        }
+    }
+
+    fn cursor(&self) -> Cursor {
+        Cursor {
+            column: self.state.column,
+            nesting: self.state.line_indent.width,
+            line_start: self.state.indent_pending,
+        }
+    }
+
+    /// Returns how many of the next `len` bytes can be written without exceeding `max_bytes`
+    fn bytes_left(&self, len: usize) -> usize {
+        match self.options.max_bytes {
+            Some(max_bytes) => cmp::min(len, max_bytes.saturating_sub(self.state.bytes)),
+            None => len,
+        }
+    }
+
+    fn write_str(&mut self, s: &str) -> Result<(), W::Error> {
+        self.state.bytes += s.len();
+        self.out.write_str_all(s)
+    }
+
+    fn push_pending_annotations(&mut self) -> Result<(), W::Error> {
+        let cursor = self.cursor();
+        for annotation in mem::take(&mut self.state.pending_annotations) {
+            self.out.push_annotation_at(annotation.borrow(), cursor)?;
+            self.state.open_annotations.push(annotation);
+        }
+        Ok(())
+    }
+
+    /// Ends the output with the elision marker, optionally on a line of its own
+    fn truncate(&mut self, on_new_line: bool) -> Result<(), W::Error> {
+        self.state.truncated = true;
+        self.state.spaces_pending = 0;
+        self.state.pending_annotations.clear();
+        if on_new_line {
+            self.out.write_str_all(&self.options.newline)?;
+            self.state.column = 0;
+        }
+        self.out.write_str_all(&self.options.elision_marker)?;
+        let cursor = self.cursor();
+        for _ in 0..self.state.open_annotations.len() {
+            self.out.pop_annotation_at(cursor)?;
+        }
+        self.state.open_annotations.clear();
+        Ok(())
+    }
+
+    pub(super) fn write_text(&mut self, s: &str, len: usize) -> Result<(), W::Error> {
+        if self.state.truncated {
+            return Ok(());
+        }
+        let text = s.trim_end_matches(' ');
+        let spaces = s.len() - text.len();
+        if self.state.line_limit_reached {
+            return if text.is_empty() {
+                Ok(())
+            } else {
+                self.truncate(true)
+            };
+        }
+        if !text.is_empty() {
+            self.write_line_text(text, len.saturating_sub(spaces))?;
+        }
+        if !self.state.truncated {
+            self.state.spaces_pending += spaces;
+        }
+        Ok(())
+    }
+
+    /// Writes text which does not end with a space, along with the indentation and spaces which
+    /// are pending before it
+    fn write_line_text(&mut self, s: &str, len: usize) -> Result<(), W::Error> {
+        if self.state.indent_pending {
+            let (tabs, spaces) = indent_parts(self.state.line_indent, self.options.indentation);
+            if self.bytes_left(tabs + spaces) < tabs + spaces {
+                return self.truncate(false);
+            }
+            self.state.bytes += tabs + spaces;
+            write_indent(self.state.line_indent, self.options.indentation, self.out)?;
+            self.state.column = self.state.line_indent.width;
+        }
+        let spaces = mem::take(&mut self.state.spaces_pending);
+        if self.bytes_left(spaces) < spaces {
+            return self.truncate(false);
+        }
+        let mut written = 0;
+        while written < spaces {
+            let chunk = cmp::min(SPACES.len(), spaces - written);
+            self.state.bytes += chunk;
+            self.out.write_text(&SPACES[..chunk], self.cursor())?;
+            self.state.column += chunk;
+            written += chunk;
+        }
+        self.push_pending_annotations()?;
+        let cursor = self.cursor();
+        self.state.indent_pending = false;
+
+        let fits = self.bytes_left(s.len());
+        if fits < s.len() {
+            let mut end = fits;
+            while !s.is_char_boundary(end) {
+                end -= 1;
+            }
+            if end != 0 {
+                self.state.bytes += end;
+                self.out.write_text(&s[..end], cursor)?;
+            }
+            return self.truncate(false);
+        }
+        self.state.bytes += s.len();
+        self.out.write_text(s, cursor)?;
+        self.state.column += len;
+        Ok(())
+    }
+
+    pub(super) fn write_newline(&mut self, indent: Indent) -> Result<(), W::Error> {
+        if self.state.truncated || self.state.line_limit_reached {
+            return Ok(());
+        }
+        self.state.spaces_pending = 0;
+        self.push_pending_annotations()?;
+        if self.options.max_lines == Some(self.state.lines) {
+            self.state.line_limit_reached = true;
+            return Ok(());
+        }
+        let options = self.options;
+        let newline = &options.newline;
+        if self.bytes_left(newline.len()) < newline.len() {
+            return self.truncate(false);
+        }
+        self.write_str(newline)?;
+        self.state.lines += 1;
+        self.state.column = 0;
+        self.state.line_indent = indent;
+        self.state.indent_pending = true;
+        self.out.newline(self.cursor())
+    }
+
+    pub(super) fn push_annotation(&mut self, annotation: P) -> Result<(), W::Error> {
+        if self.state.truncated {
+            return Ok(());
+        }
+        if self.state.indent_pending
+            || self.state.spaces_pending != 0
+            || self.state.line_limit_reached
+        {
+            self.state.pending_annotations.push(annotation);
+            Ok(())
+        } else {
+            self.out
+                .push_annotation_at(annotation.borrow(), self.cursor())?;
+            self.state.open_annotations.push(annotation);
+            Ok(())
+        }
+    }
+
+    pub(super) fn pop_annotation(&mut self) -> Result<(), W::Error> {
+        if self.state.truncated {
+            return Ok(());
+        }
+        if self.state.line_limit_reached {
+            // Annotations pushed after the last line are never written
+            if self.state.pending_annotations.pop().is_some() {
+                return Ok(());
+            }
+        } else {
+            self.push_pending_annotations()?;
+        }
+        self.state.open_annotations.pop();
+        self.out.pop_annotation_at(self.cursor())
+    }
+
+    pub(super) fn is_done(&self) -> bool {
+        self.state.is_truncated()
+    }
+
+    pub(super) fn checkpoint(&mut self) -> Option<usize>
+    where
+        P: Clone,
+    {
+        let checkpoint = self.out.rewind()?.checkpoint();
+        let state = self.state.snapshot();
+        self.state.checkpoints.push((checkpoint, state));
+        Some(self.state.checkpoints.len() - 1)
+    }
+
+    pub(super) fn rollback(&mut self, checkpoint: usize) -> Result<(), W::Error> {
+        self.state.checkpoints.truncate(checkpoint + 1);
+        let (checkpoint, state) = self.state.checkpoints.pop().unwrap();
+        let checkpoints = mem::replace(&mut *self.state, state).checkpoints;
+        self.state.checkpoints = checkpoints;
+        match self.out.rewind() {
+            Some(out) => out.rollback(checkpoint),
+            // Checkpoints are only created for outputs which can be rolled back
+            None => Err(self.out.fail_doc()),
+        }
+    }
+
+    pub(super) fn commit(&mut self, checkpoint: usize) {
+        self.state.checkpoints.truncate(checkpoint);
+    }
+
+    /// Creates the error for a `fail` document inside the annotations which are currently open
+    pub(super) fn fail_doc(&self, message: Option<&str>) -> W::Error {
+        let annotations = self.state.annotations().map(Borrow::borrow).collect();
+        self.out
+            .fail_doc_with(RenderError::new(message, annotations))
+    }
+}
+
+impl<W, A> Output for Writer<'_, W, &A, A>
+where
+    W: ?Sized,
+    for<'b> W: RenderAnnotated<'b, A>,
+{
+    type Error = W::Error;
+
+    fn write_text(&mut self, s: &str, len: usize) -> Result<(), Self::Error> {
+        Writer::write_text(self, s, len)
+    }
+
+    fn write_newline(&mut self, indent: Indent) -> Result<(), Self::Error> {
+        Writer::write_newline(self, indent)
+    }
+
+    fn fail_doc(&self, message: Option<&str>) -> Self::Error {
+        Writer::fail_doc(self, message)
+    }
+
+    fn is_done(&self) -> bool {
+        Writer::is_done(self)
+    }
+
+    fn checkpoint(&mut self) -> Option<usize> {
+        Writer::checkpoint(self)
+    }
+
+    fn rollback(&mut self, checkpoint: usize) -> Result<(), Self::Error> {
+        Writer::rollback(self, checkpoint)
+    }
+
+    fn commit(&mut self, checkpoint: usize) {
+        Writer::commit(self, checkpoint)
+    }
+}
+
+impl<'d, W, A> OutputAnnotated<'d, A> for Writer<'_, W, &'d A, A>
+where
+    W: ?Sized,
+    for<'b> W: RenderAnnotated<'b, A>,
+{
+    fn push_annotation(&mut self, annotation: &'d A) -> Result<(), Self::Error> {
+        Writer::push_annotation(self, annotation)
+    }
+
+    fn pop_annotation(&mut self) -> Result<(), Self::Error> {
+        Writer::pop_annotation(self)
+    }
+}
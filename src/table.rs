@@ -0,0 +1,685 @@
+//! Document formatting of tables, where the cells of every row are aligned into columns. See
+//! `Table` for an example
+
+use std::{cell::RefCell, cmp, collections::HashMap, mem, rc::Rc};
+
+use crate::{
+    pad_to, BuildDoc, Doc, DocAllocator, DocBuilder, DocPtr, Layout, LayoutToken, Pretty,
+    RenderOptions,
+};
+
+/// How the contents of a column are aligned when they are narrower than the column
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Alignment {
+    #[default]
+    Left,
+    Right,
+    Center,
+}
+
+/// Formats rows of documents as a table whose columns are as wide as their widest cell, as
+/// measured by laying the cells out with the `RenderOptions` given to `format` (so wide
+/// characters are measured by its `width_measure`, and text given a width with `Doc::RenderLen`
+/// takes up that many columns).
+///
+/// If the table does not fit in the rest of the page the widest columns are narrowed, down to the
+/// width of their cells when every group in them is broken, and cells which do not fit in their
+/// column are laid out over several lines.
+///
+/// ```
+/// use pretty::{Alignment, DocAllocator, RenderOptions};
+///
+/// let arena = pretty::Arena::<()>::new();
+/// let options = RenderOptions::new(80);
+/// let table = arena
+///     .table([["serde", "1.0.197"], ["pretty", "0.12.3"]])
+///     .header(["name", "version"])
+///     .alignments([Alignment::Left, Alignment::Right])
+///     .format(&options);
+///
+/// assert_eq!(
+///     table.1.pretty_with(options).to_string(),
+///     "name    version\n------  -------\nserde   1.0.197\npretty   0.12.3",
+/// );
+/// ```
+///
+/// With borders:
+///
+/// ```
+/// use pretty::{DocAllocator, RenderOptions};
+///
+/// let arena = pretty::Arena::<()>::new();
+/// let options = RenderOptions::new(80);
+/// let table = arena
+///     .table([["serde", "1.0.197"], ["pretty", "0.12.3"]])
+///     .header(["name", "version"])
+///     .borders(true)
+///     .format(&options);
+///
+/// assert_eq!(
+///     table.1.pretty_with(options).to_string(),
+///     "\
+/// +--------+---------+
+/// | name   | version |
+/// +--------+---------+
+/// | serde  | 1.0.197 |
+/// | pretty | 0.12.3  |
+/// +--------+---------+",
+/// );
+/// ```
+pub struct Table<'doc, D, A>
+where
+    D: DocAllocator<'doc, A>,
+{
+    allocator: &'doc D,
+    rows: Vec<Vec<DocBuilder<'doc, D, A>>>,
+    header: Option<Vec<DocBuilder<'doc, D, A>>>,
+    alignments: Vec<Alignment>,
+    separator: Option<DocBuilder<'doc, D, A>>,
+    borders: bool,
+}
+
+impl<'doc, D, A> Table<'doc, D, A>
+where
+    D: DocAllocator<'doc, A>,
+{
+    pub fn new(allocator: &'doc D, rows: Vec<Vec<DocBuilder<'doc, D, A>>>) -> Self {
+        Table {
+            allocator,
+            rows,
+            header: None,
+            alignments: Vec::new(),
+            separator: None,
+            borders: false,
+        }
+    }
+
+    /// Sets the cells of a row which is placed above the other rows and set apart from them by a
+    /// rule.
+    pub fn header<I>(mut self, header: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Pretty<'doc, D, A>,
+    {
+        let allocator = self.allocator;
+        self.header = Some(
+            header
+                .into_iter()
+                .map(|doc| doc.pretty(allocator))
+                .collect(),
+        );
+        self
+    }
+
+    /// Sets the alignment of each column, starting with the first. Columns without an alignment
+    /// are aligned to the left.
+    pub fn alignments(mut self, alignments: impl IntoIterator<Item = Alignment>) -> Self {
+        self.alignments = alignments.into_iter().collect();
+        self
+    }
+
+    /// Sets the document placed between the cells of a row. Defaults to two spaces and is not
+    /// used when the table has borders.
+    pub fn separator(mut self, separator: impl Pretty<'doc, D, A>) -> Self {
+        self.separator = Some(separator.pretty(self.allocator));
+        self
+    }
+
+    /// Draws lines around the table and between its columns.
+    pub fn borders(mut self, borders: bool) -> Self {
+        self.borders = borders;
+        self
+    }
+}
+
+impl<'doc, D, A> Table<'doc, D, A>
+where
+    D: DocAllocator<'doc, A>,
+    D::Doc: Clone,
+    A: Clone,
+{
+    /// Creates the document for the table. The cells are measured and laid out with `options`
+    /// (which should use the same width measure as the options the table is rendered with), while
+    /// the widths of the columns are chosen when the table is rendered, so that it fits in the
+    /// rest of the page.
+    pub fn format(self, options: &RenderOptions) -> DocBuilder<'doc, D, A> {
+        let Table {
+            allocator,
+            rows,
+            header,
+            alignments,
+            separator,
+            borders,
+        } = self;
+
+        let columns = header.iter().chain(&rows).map(Vec::len).max().unwrap_or(0);
+        if columns == 0 {
+            return allocator.nil();
+        }
+        let row_docs =
+            |row: Vec<DocBuilder<'doc, D, A>>| row.into_iter().map(|doc| doc.1).collect();
+        let mut cells = Cells {
+            header: header.map(row_docs),
+            rows: rows.into_iter().map(row_docs).collect(),
+            alignments,
+            separator: separator.unwrap_or_else(|| allocator.text("  ")).1,
+            borders,
+            widths: vec![0; columns],
+            minimums: vec![0; columns],
+            separator_width: 3,
+            options: options.clone(),
+            formatted: RefCell::new(HashMap::new()),
+        };
+        if cells.measure().is_err() {
+            return allocator.fail();
+        }
+
+        let widest = cells.table_width(&cells.widths);
+        Rc::new(cells).fitting(allocator, widest).align()
+    }
+}
+
+/// The contents of a `Table`, laid out once the space left for it is known
+struct Cells<'doc, D, A>
+where
+    D: DocAllocator<'doc, A>,
+{
+    header: Option<Vec<BuildDoc<'doc, D::Doc, A>>>,
+    rows: Vec<Vec<BuildDoc<'doc, D::Doc, A>>>,
+    alignments: Vec<Alignment>,
+    separator: BuildDoc<'doc, D::Doc, A>,
+    borders: bool,
+    // The width of each column when nothing in it is broken, and the narrowest it can be
+    widths: Vec<usize>,
+    minimums: Vec<usize>,
+    separator_width: usize,
+    options: RenderOptions,
+    // The table for each width it has been laid out in
+    formatted: RefCell<HashMap<usize, D::Doc>>,
+}
+
+impl<'doc, D, A> Cells<'doc, D, A>
+where
+    D: DocAllocator<'doc, A> + 'doc,
+    D::Doc: Clone,
+    A: Clone,
+{
+    fn measure(&mut self) -> Result<(), ()> {
+        if !self.borders {
+            self.separator_width =
+                widest_line(&layout(&self.options, &self.separator, usize::MAX)?);
+        }
+        let mut widths = mem::take(&mut self.widths);
+        let mut minimums = mem::take(&mut self.minimums);
+        for row in self.header.iter().chain(&self.rows) {
+            for (i, cell) in row.iter().enumerate() {
+                let width = widest_line(&layout(&self.options, cell, usize::MAX)?);
+                let minimum = widest_line(&layout(&self.options, cell, 0)?);
+                widths[i] = cmp::max(widths[i], width);
+                minimums[i] = cmp::max(minimums[i], cmp::min(minimum, width));
+            }
+        }
+        self.widths = widths;
+        self.minimums = minimums;
+        Ok(())
+    }
+
+    /// The number of columns taken up by the table when its columns are `widths` wide
+    fn table_width(&self, widths: &[usize]) -> usize {
+        let columns = widths.len();
+        let overhead = if self.borders {
+            3 * columns + 1
+        } else {
+            self.separator_width * (columns - 1)
+        };
+        widths.iter().sum::<usize>() + overhead
+    }
+
+    /// The table laid out in `available` columns, or in fewer columns if that does not fit in
+    /// the rest of the page when it is rendered
+    fn fitting(self: &Rc<Self>, allocator: &'doc D, available: usize) -> DocBuilder<'doc, D, A> {
+        let doc = DocBuilder(
+            allocator,
+            BuildDoc::DocPtr(self.format(allocator, available)),
+        );
+        if available <= self.table_width(&self.minimums) {
+            return doc;
+        }
+        // The narrower tables are only created if they are needed
+        let cells = self.clone();
+        doc.union(allocator.column(move |_| cells.fitting(allocator, available - 1).into_doc()))
+    }
+
+    fn format(&self, allocator: &'doc D, available: usize) -> D::Doc {
+        if let Some(doc) = self.formatted.borrow().get(&available) {
+            return doc.clone();
+        }
+        let doc = self
+            .lines(allocator, available)
+            .map(|lines| allocator.intersperse(lines, allocator.hardline()))
+            .unwrap_or_else(|()| allocator.fail())
+            .into_doc();
+        self.formatted.borrow_mut().insert(available, doc.clone());
+        doc
+    }
+
+    fn lines(
+        &self,
+        allocator: &'doc D,
+        available: usize,
+    ) -> Result<Vec<DocBuilder<'doc, D, A>>, ()> {
+        // Narrow the widest columns one at a time until the table fits
+        let columns = self.widths.len();
+        let mut widths = self.widths.clone();
+        let mut total = self.table_width(&widths);
+        while total > available {
+            let widest = (0..columns)
+                .filter(|&i| widths[i] > self.minimums[i])
+                .max_by_key(|&i| (widths[i], cmp::Reverse(i)));
+            match widest {
+                Some(i) => widths[i] -= 1,
+                None => break,
+            }
+            total -= 1;
+        }
+
+        let rule = |pad| {
+            let rule = widths.iter().map(|&width| pad_to(allocator, width, 0, pad));
+            if self.borders {
+                allocator
+                    .text("+-")
+                    .append(allocator.intersperse(rule, "-+-"))
+                    .append("-+")
+            } else {
+                allocator.intersperse(rule, pad_to(allocator, self.separator_width, 0, ' '))
+            }
+        };
+
+        let mut lines = Vec::new();
+        if self.borders {
+            lines.push(rule('-'));
+        }
+        if let Some(header) = &self.header {
+            self.row(allocator, header, &widths, &mut lines)?;
+            lines.push(rule('-'));
+        }
+        for row in &self.rows {
+            self.row(allocator, row, &widths, &mut lines)?;
+        }
+        if self.borders && !self.rows.is_empty() {
+            lines.push(rule('-'));
+        }
+        Ok(lines)
+    }
+
+    /// Lays out the cells of `row` in their columns, pushing the lines of the row to `lines`
+    fn row(
+        &self,
+        allocator: &'doc D,
+        row: &[BuildDoc<'doc, D::Doc, A>],
+        widths: &[usize],
+        lines: &mut Vec<DocBuilder<'doc, D, A>>,
+    ) -> Result<(), ()> {
+        let cells = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, &width)| Ok(cell_lines(allocator, &layout(&self.options, cell, width)?)))
+            .collect::<Result<Vec<_>, ()>>()?;
+        let height = cells.iter().map(Vec::len).max().unwrap_or(1);
+
+        for line in 0..height {
+            let mut doc = if self.borders {
+                allocator.text("| ")
+            } else {
+                allocator.nil()
+            };
+            // Without borders, the columns which are empty at the end of the line are left out
+            // to avoid trailing whitespace
+            let columns = if self.borders {
+                widths.len()
+            } else {
+                1 + (0..widths.len())
+                    .rev()
+                    .find(|&i| {
+                        cells
+                            .get(i)
+                            .and_then(|cell| cell.get(line))
+                            .is_some_and(|&(_, width)| width != 0)
+                    })
+                    .unwrap_or(0)
+            };
+            for (i, &width) in widths.iter().enumerate().take(columns) {
+                if i != 0 {
+                    doc = if self.borders {
+                        doc.append(" | ")
+                    } else {
+                        doc.append(DocBuilder(allocator, self.separator.clone()))
+                    };
+                }
+                let (cell, cell_width) = cells
+                    .get(i)
+                    .and_then(|cell| cell.get(line).cloned())
+                    .unwrap_or_else(|| (allocator.nil(), 0));
+                let padding = width.saturating_sub(cell_width);
+                let (before, after) = match self.alignments.get(i).copied().unwrap_or_default() {
+                    Alignment::Left => (0, padding),
+                    Alignment::Right => (padding, 0),
+                    Alignment::Center => (padding / 2, padding - padding / 2),
+                };
+                let after = if self.borders || i + 1 != columns {
+                    after
+                } else {
+                    0
+                };
+                doc = doc
+                    .append(pad_to(allocator, before, 0, ' '))
+                    .append(cell)
+                    .append(pad_to(allocator, after, 0, ' '));
+            }
+            if self.borders {
+                doc = doc.append(" |");
+            }
+            lines.push(doc);
+        }
+        Ok(())
+    }
+}
+
+/// Lays out `doc` as if it was rendered with `options` on a page which is `width` columns wide
+fn layout<'doc, T, A>(
+    options: &RenderOptions,
+    doc: &BuildDoc<'doc, T, A>,
+    width: usize,
+) -> Result<Layout<A>, ()>
+where
+    T: DocPtr<'doc, A> + 'doc,
+    A: Clone,
+{
+    doc.layout_with(&options.with_page_width(width))
+        .map_err(|_| ())
+}
+
+/// Returns the number of columns taken up by the widest line of `layout`
+fn widest_line<A>(layout: &Layout<A>) -> usize {
+    let mut widest = 0;
+    let mut width = 0;
+    let mut indent = 0;
+    for (token, len) in layout.measured_tokens() {
+        match token {
+            LayoutToken::Text(_) => {
+                width += mem::take(&mut indent) + len;
+                widest = cmp::max(widest, width);
+            }
+            LayoutToken::Newline { indent: i, .. } => {
+                width = 0;
+                indent = i;
+            }
+            LayoutToken::PushAnnotation(_) | LayoutToken::PopAnnotation => (),
+        }
+    }
+    widest
+}
+
+/// Turns each line of `layout` into a document along with its width. Annotations which span
+/// several lines are applied to each of them.
+fn cell_lines<'doc, D, A>(
+    allocator: &'doc D,
+    layout: &Layout<A>,
+) -> Vec<(DocBuilder<'doc, D, A>, usize)>
+where
+    D: DocAllocator<'doc, A>,
+    A: Clone,
+{
+    // The annotations which are open along with the part of the current line inside them,
+    // outermost first
+    let mut open = vec![(None, allocator.nil())];
+    let close = |open: &mut Vec<(Option<A>, DocBuilder<'doc, D, A>)>| {
+        let mut doc = allocator.nil();
+        for (annotation, part) in open.iter_mut().rev() {
+            let part = mem::replace(part, allocator.nil()).append(doc);
+            doc = match annotation {
+                Some(annotation) => part.annotate(annotation.clone()),
+                None => part,
+            };
+        }
+        doc
+    };
+
+    let mut lines = Vec::new();
+    let mut width = 0;
+    let mut indent = 0;
+    for (token, len) in layout.measured_tokens() {
+        match token {
+            LayoutToken::Text(text) => {
+                if indent != 0 {
+                    let part = mem::replace(&mut open[0].1, allocator.nil());
+                    open[0].1 = part.append(pad_to(allocator, indent, 0, ' '));
+                    width += mem::take(&mut indent);
+                }
+                let text = allocator.alloc(Doc::OwnedText(text.into()));
+                let text = Doc::RenderLen(len, text);
+                let (_, part) = open.last_mut().unwrap();
+                *part = mem::replace(part, allocator.nil()).append(text);
+                width += len;
+            }
+            LayoutToken::Newline { indent: i, .. } => {
+                lines.push((close(&mut open), width));
+                width = 0;
+                indent = i;
+            }
+            LayoutToken::PushAnnotation(annotation) => {
+                open.push((Some(annotation.clone()), allocator.nil()));
+            }
+            LayoutToken::PopAnnotation => {
+                let (annotation, part) = open.pop().unwrap();
+                let (_, parent) = open.last_mut().unwrap();
+                let part = match annotation {
+                    Some(annotation) => part.annotate(annotation),
+                    None => part,
+                };
+                *parent = mem::replace(parent, allocator.nil()).append(part);
+            }
+        }
+    }
+    lines.push((close(&mut open), width));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::{Alignment, Table};
+
+    use crate::{Arena, Doc, DocAllocator, DocBuilder, LayoutToken, RenderOptions, UnicodeWidth};
+
+    fn render<'a>(table: Table<'a, Arena<'a, ()>, ()>, options: RenderOptions) -> String {
+        table.format(&options).1.pretty_with(options).to_string()
+    }
+
+    #[test]
+    fn table() {
+        let arena = &Arena::<()>::new();
+
+        assert_eq!(
+            render(
+                arena
+                    .table([["a", "bbb"], ["ccc", "d"]])
+                    .alignments([Alignment::Center, Alignment::Center])
+                    .separator(" | "),
+                RenderOptions::new(80)
+            ),
+            " a  | bbb\nccc |  d"
+        );
+
+        // Rows may have fewer cells than there are columns
+        assert_eq!(
+            render(
+                arena.table(vec![vec!["a"], vec!["b", "c"]]).borders(true),
+                RenderOptions::new(80)
+            ),
+            "+---+---+\n| a |   |\n| b | c |\n+---+---+"
+        );
+
+        // The rest of the table is aligned with its first line
+        let options = RenderOptions::new(80);
+        assert_eq!(
+            arena
+                .text("- ")
+                .append(arena.table([["a", "1"], ["bc", "2"]]).format(&options))
+                .1
+                .pretty_with(options)
+                .to_string(),
+            "- a   1\n  bc  2"
+        );
+
+        assert_eq!(
+            render(arena.table(Vec::<Vec<&str>>::new()), RenderOptions::new(80)),
+            ""
+        );
+    }
+
+    #[test]
+    fn table_widths() {
+        let arena = &Arena::<()>::new();
+
+        assert_eq!(
+            render(
+                arena.table([["日本", "x"], ["abc", "y"]]),
+                RenderOptions::new(80)
+            ),
+            "日本  x\nabc   y"
+        );
+
+        // Cells are measured with the width measure of the options
+        let table = || arena.table([["°", "x"], ["ab", "y"]]);
+        assert_eq!(render(table(), RenderOptions::new(80)), "°   x\nab  y");
+        let options =
+            RenderOptions::new(80).width_measure(UnicodeWidth::new().ambiguous_wide(true));
+        assert_eq!(render(table(), options), "°  x\nab  y");
+
+        let render_len = DocBuilder(arena, Doc::RenderLen(1, arena.text("ab").into_doc()).into());
+        assert_eq!(
+            render(
+                arena.table([
+                    [render_len, arena.text("x")],
+                    [arena.text("c"), arena.text("y")]
+                ]),
+                RenderOptions::new(80)
+            ),
+            "ab  x\nc  y"
+        );
+    }
+
+    #[test]
+    fn table_wraps_cells() {
+        let arena = &Arena::<()>::new();
+        let table = || {
+            arena.table([
+                [
+                    arena.text("name"),
+                    arena.reflow("a long description of the item"),
+                ],
+                [arena.text("id"), arena.text("1")],
+            ])
+        };
+
+        assert_eq!(
+            render(table(), RenderOptions::new(80)),
+            "name  a long description of the item\nid    1"
+        );
+        assert_eq!(
+            render(table(), RenderOptions::new(20)),
+            "name  a long\n      description of\n      the item\nid    1"
+        );
+        // Columns are not narrowed below their widest unbreakable part
+        assert_eq!(
+            render(table(), RenderOptions::new(4)),
+            "name  a long\n      description\n      of the item\nid    1"
+        );
+    }
+
+    #[test]
+    fn table_fits_the_page_it_is_rendered_in() {
+        let arena = &Arena::<()>::new();
+        let table = arena
+            .table([
+                [arena.text("aaaa"), arena.text("b")],
+                [arena.text("c"), arena.reflow("d e")],
+            ])
+            .format(&RenderOptions::new(80));
+
+        assert_eq!(
+            table.1.pretty(80).to_string(),
+            "aaaa  b
+c     d e"
+        );
+        assert_eq!(
+            table.1.pretty(8).to_string(),
+            "aaaa  b
+c     d
+      e"
+        );
+        let options = RenderOptions::new(8).optimal(crate::DefaultCost::new());
+        assert_eq!(
+            table.1.pretty_with(options).to_string(),
+            "aaaa  b\nc     d\n      e"
+        );
+    }
+
+    #[test]
+    fn table_is_laid_out_once_per_width() {
+        let arena = &Arena::<()>::new();
+        let layouts = Cell::new(0);
+        let cell = arena.column(|_| {
+            layouts.set(layouts.get() + 1);
+            arena.text("a").into_doc()
+        });
+        let options = RenderOptions::new(80);
+        let table = arena.table([[cell, arena.reflow("b c")]]).format(&options);
+        let laid_out = layouts.get();
+
+        // The table is only laid out again for narrower pages, once for each width
+        let mut doc = table;
+        for _ in 0..8 {
+            doc = doc.clone().union(doc);
+        }
+        assert_eq!(doc.1.pretty_with(options).to_string(), "a  b c");
+        assert_eq!(layouts.get(), laid_out);
+        assert_eq!(doc.1.pretty(4).to_string(), "a  b\n   c");
+        assert_eq!(doc.1.pretty(4).to_string(), "a  b\n   c");
+        assert_eq!(layouts.get(), laid_out + 1);
+    }
+
+    #[test]
+    fn table_annotations_span_lines() {
+        let arena = &Arena::<&str>::new();
+        let cell = arena
+            .text("a")
+            .append(arena.hardline())
+            .append("b")
+            .annotate("cell");
+        let options = RenderOptions::new(80);
+        let doc = arena.table([[cell, arena.text("c")]]).format(&options);
+        let layout = doc.1.layout_with(&options).unwrap();
+
+        assert_eq!(layout.to_string(), "a  c\nb");
+        assert_eq!(
+            layout.tokens().collect::<Vec<_>>(),
+            [
+                LayoutToken::PushAnnotation(&"cell"),
+                LayoutToken::Text("a"),
+                LayoutToken::PopAnnotation,
+                LayoutToken::Text("  "),
+                LayoutToken::Text("c"),
+                LayoutToken::Newline {
+                    indent: 0,
+                    align: 0
+                },
+                LayoutToken::PushAnnotation(&"cell"),
+                LayoutToken::Text("b"),
+                LayoutToken::PopAnnotation,
+            ]
+        );
+    }
+}